@@ -0,0 +1,124 @@
+use crate::options::Encoding;
+
+// decode raw input bytes into text; `Auto` goes by the byte order mark and
+// falls back to UTF-8, and a mark matching the encoding is always dropped
+pub fn decode(bytes: &[u8], encoding: Encoding) -> Result<String, String> {
+    let encoding = match encoding {
+        Encoding::Auto if bytes.starts_with(&[0xff, 0xfe]) => Encoding::Utf16Le,
+        Encoding::Auto if bytes.starts_with(&[0xfe, 0xff]) => Encoding::Utf16Be,
+        Encoding::Auto => Encoding::Utf8,
+        explicit => explicit,
+    };
+
+    match encoding {
+        Encoding::Utf16Le => decode_utf16(bytes.strip_prefix(&[0xff, 0xfe]).unwrap_or(bytes), u16::from_le_bytes),
+        Encoding::Utf16Be => decode_utf16(bytes.strip_prefix(&[0xfe, 0xff]).unwrap_or(bytes), u16::from_be_bytes),
+        _ => {
+            let bytes = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]).unwrap_or(bytes);
+            String::from_utf8(bytes.to_vec()).map_err(|_| "input is not valid UTF-8".to_string())
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Result<String, String> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err("UTF-16 input has an odd number of bytes".to_string());
+    }
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]])).collect();
+    String::from_utf16(&units).map_err(|_| "input is not valid UTF-16".to_string())
+}
+
+// undo JSON string escaping (`\"`, `\\`, `\n`, `\u00e9`, ...), for shell
+// text that was stored inside a JSON string
+pub fn json_unescape(text: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some('/') => out.push('/'),
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('u') => {
+                let unit = hex_unit(&mut chars)?;
+                // characters outside the BMP come as a surrogate pair
+                let code = if (0xd800..0xdc00).contains(&unit) {
+                    if chars.next() != Some('\\') || chars.next() != Some('u') {
+                        return Err("unpaired surrogate in \\u escape".to_string());
+                    }
+                    let low = hex_unit(&mut chars)?;
+                    if !(0xdc00..0xe000).contains(&low) {
+                        return Err("unpaired surrogate in \\u escape".to_string());
+                    }
+                    0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00)
+                } else {
+                    unit
+                };
+                out.push(char::from_u32(code).ok_or_else(|| "unpaired surrogate in \\u escape".to_string())?);
+            }
+            Some(other) => return Err(format!("unknown escape \\{}", other)),
+            None => return Err("input ends with a lone backslash".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+// the four hex digits of a `\uXXXX` escape
+fn hex_unit(chars: &mut std::str::Chars) -> Result<u32, String> {
+    let digits: String = chars.take(4).collect();
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("bad \\u escape \\u{}", digits));
+    }
+    u32::from_str_radix(&digits, 16).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_posix_exports, Export};
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend(text.encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+        bytes
+    }
+
+    #[test]
+    fn test_utf16le_with_bom() {
+        let input = decode(&utf16le("export NAME=José\r\nexport B=2"), Encoding::Auto).unwrap();
+        assert_eq!(parse_posix_exports(&input), vec![Export::new("NAME", "José"), Export::new("B", "2")]);
+    }
+
+    #[test]
+    fn test_explicit_encodings() {
+        let be: Vec<u8> = "export A=1".encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect();
+        assert_eq!(decode(&be, Encoding::Utf16Be).unwrap(), "export A=1");
+        assert_eq!(decode(b"\xef\xbb\xbfexport A=1", Encoding::Auto).unwrap(), "export A=1");
+        assert!(decode(b"\xff\xfe\x41", Encoding::Auto).is_err());
+        assert!(decode(b"\xff\xfe", Encoding::Utf8).is_err());
+    }
+
+    #[test]
+    fn test_json_unescape() {
+        let input = json_unescape(r#"export FOO=\"bar baz\"\nexport PATH=\"C:\\\\bin\" NAME=Jos\u00e9 CLEF=\ud834\udd1e"#).unwrap();
+        assert_eq!(input, "export FOO=\"bar baz\"\nexport PATH=\"C:\\\\bin\" NAME=José CLEF=𝄞");
+        assert_eq!(parse_posix_exports(&input), vec![
+            Export::new("FOO", "bar baz"),
+            Export::new("PATH", "C:\\bin"),
+            Export::new("NAME", "José"),
+            Export::new("CLEF", "𝄞"),
+        ]);
+        assert!(json_unescape(r"\q").is_err());
+        assert!(json_unescape(r"\u12").is_err());
+        assert!(json_unescape(r"\ud834").is_err());
+        assert!(json_unescape("tail\\").is_err());
+    }
+}
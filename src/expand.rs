@@ -0,0 +1,413 @@
+// expand `$NAME`, `${NAME}` and `${!NAME}` references in a value, looking
+// names up through `lookup`; anything that can't be resolved is kept as written
+pub fn expand_value(value: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+    expand_value_tracked(value, lookup, &mut Vec::new(), true)
+}
+
+// like `expand_value`, also recording every reference left unexpanded;
+// without `keep_unresolved` they expand to nothing, as in a shell
+pub fn expand_value_tracked(
+    value: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    unresolved: &mut Vec<String>,
+    keep_unresolved: bool,
+) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(pos) = rest.find(['$', '\\']) {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        // `\$` is a literal dollar sign
+        if let Some(after) = rest.strip_prefix("\\$") {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        if rest.starts_with('\\') {
+            out.push('\\');
+            rest = &rest[1..];
+            continue;
+        }
+
+        let (expanded, consumed) = match expand_reference(rest, lookup, unresolved, keep_unresolved) {
+            (Some(expanded), consumed) => (expanded, consumed),
+            (None, consumed) => {
+                unresolved.push(rest[..consumed].to_string());
+                let kept = if keep_unresolved { &rest[..consumed] } else { "" };
+                (kept.to_string(), consumed)
+            }
+        };
+        out.push_str(&expanded);
+        rest = &rest[consumed..];
+    }
+    out.push_str(rest);
+
+    out
+}
+
+// expand the reference at the start of `text` (which begins with `$`),
+// returning the replacement (`None` if it couldn't be resolved) and how many
+// bytes of `text` it covered
+fn expand_reference(
+    text: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    unresolved: &mut Vec<String>,
+    keep_unresolved: bool,
+) -> (Option<String>, usize) {
+    let after = &text[1..];
+
+    // `$((expr))`, only when both parentheses are closed; an unbalanced
+    // opener is left alone as literal text
+    if after.starts_with("((") {
+        if let (Some(outer), Some(inner)) = (matching_paren(text, 1), matching_paren(text, 2)) {
+            if inner + 1 == outer {
+                let consumed = outer + 1;
+                let result = eval_arithmetic(&text[3..inner], lookup);
+                return (result.map(|n| n.to_string()), consumed);
+            }
+        }
+        return (None, 3);
+    }
+
+    if after.starts_with('{') {
+        let Some(close) = matching_brace(text, 1) else {
+            return (None, 2);
+        };
+        let inner = &text[2..close];
+        let consumed = close + 1;
+        let name_len = name_prefix_len(inner);
+        let resolved = match inner.strip_prefix('!') {
+            // indirect: `${!ref}` is the variable named by the value of `ref`
+            Some(reference) if is_name(reference) => {
+                lookup(reference).filter(|target| is_name(target)).and_then(|target| lookup(&target))
+            }
+            _ if name_len == inner.len() && name_len > 0 => lookup(inner),
+            // `${NAME:-word}` and friends
+            _ if name_len > 0 => {
+                let current = lookup(&inner[..name_len]);
+                match parse_operator(&inner[name_len..]) {
+                    Some((op, word)) => apply_operator(current, op, word, lookup, unresolved, keep_unresolved),
+                    None => None,
+                }
+            }
+            _ => None,
+        };
+        return (resolved, consumed);
+    }
+
+    let name_len = name_prefix_len(after);
+    if name_len == 0 {
+        // a lone `$` is just a dollar sign
+        return (Some("$".to_string()), 1);
+    }
+    (lookup(&after[..name_len]), name_len + 1)
+}
+
+// split `:-word`, `-word`, `:=word`, `=word`, `:+word` or `+word` into the
+// operator (without the colon), whether the colon was there, and the word
+pub(crate) fn parse_operator(rest: &str) -> Option<((char, bool), &str)> {
+    let (colon, rest) = match rest.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let op = rest.chars().next().filter(|c| matches!(c, '-' | '=' | '+'))?;
+    Some(((op, colon), &rest[1..]))
+}
+
+// with the colon, an empty value counts as unset
+fn apply_operator(
+    current: Option<String>,
+    (op, colon): (char, bool),
+    word: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    unresolved: &mut Vec<String>,
+    keep_unresolved: bool,
+) -> Option<String> {
+    let set = current.as_ref().is_some_and(|v| !colon || !v.is_empty());
+    match op {
+        // `-` and `=` use the word when unset; we can't assign back, so `=`
+        // only affects this value
+        '-' | '=' if set => current,
+        '-' | '=' => Some(expand_word(word, lookup, unresolved, keep_unresolved)),
+        _ if set => Some(expand_word(word, lookup, unresolved, keep_unresolved)),
+        _ => Some(String::new()),
+    }
+}
+
+// expand the word of a `${NAME:-word}`: quotes are removed, single-quoted
+// text is literal and everything else is expanded
+fn expand_word(
+    word: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    unresolved: &mut Vec<String>,
+    keep_unresolved: bool,
+) -> String {
+    let mut out = String::new();
+    let mut rest = word;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('\'') {
+            let end = after.find('\'').unwrap_or(after.len());
+            out.push_str(&after[..end]);
+            rest = after.get(end + 1..).unwrap_or("");
+        } else if let Some(after) = rest.strip_prefix('"') {
+            let end = after.find('"').unwrap_or(after.len());
+            out.push_str(&expand_value_tracked(&after[..end], lookup, unresolved, keep_unresolved));
+            rest = after.get(end + 1..).unwrap_or("");
+        } else {
+            let end = rest.find(['\'', '"']).unwrap_or(rest.len());
+            out.push_str(&expand_value_tracked(&rest[..end], lookup, unresolved, keep_unresolved));
+            rest = &rest[end..];
+        }
+    }
+    out
+}
+
+// byte index of the `}` closing the `{` at `open`, skipping over quoted text
+// and nested braces
+pub(crate) fn matching_brace(text: &str, open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    for (i, ch) in text[open..].char_indices() {
+        match (quote, ch) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, '{') => depth += 1,
+            (None, '}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open + i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// byte index of the `)` closing the `(` at `open`, if it's ever closed
+pub(crate) fn matching_paren(text: &str, open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, ch) in text[open..].char_indices() {
+        match ch {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open + i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// evaluate a shell arithmetic expression over integers; `None` for anything
+// malformed, overflowing or dividing by zero
+fn eval_arithmetic(expr: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Option<i64> {
+    let tokens: Vec<char> = expr.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut arith = Arithmetic { tokens: &tokens, pos: 0, depth: 0, lookup };
+    let value = arith.expr()?;
+    (arith.pos == tokens.len()).then_some(value)
+}
+
+// deeper nesting than this is treated as malformed rather than recursed into
+const MAX_ARITHMETIC_DEPTH: usize = 256;
+
+struct Arithmetic<'a> {
+    tokens: &'a [char],
+    pos: usize,
+    // nested parentheses and unary signs, bounded by MAX_ARITHMETIC_DEPTH
+    depth: usize,
+    lookup: &'a dyn Fn(&str) -> Option<String>,
+}
+
+impl Arithmetic<'_> {
+    fn peek(&self) -> Option<char> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn expr(&mut self) -> Option<i64> {
+        let mut value = self.term()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = if op == '+' { value.checked_add(rhs)? } else { value.checked_sub(rhs)? };
+        }
+        Some(value)
+    }
+
+    fn term(&mut self) -> Option<i64> {
+        let mut value = self.unary()?;
+        while let Some(op @ ('*' | '/' | '%')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.unary()?;
+            value = match op {
+                '*' => value.checked_mul(rhs)?,
+                '/' => value.checked_div(rhs)?,
+                _ => value.checked_rem(rhs)?,
+            };
+        }
+        Some(value)
+    }
+
+    fn unary(&mut self) -> Option<i64> {
+        if self.depth >= MAX_ARITHMETIC_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let value = self.signed();
+        self.depth -= 1;
+        value
+    }
+
+    fn signed(&mut self) -> Option<i64> {
+        match self.peek()? {
+            '-' => {
+                self.pos += 1;
+                self.unary()?.checked_neg()
+            }
+            '+' => {
+                self.pos += 1;
+                self.unary()
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Option<i64> {
+        let ch = self.peek()?;
+        if ch == '(' {
+            self.pos += 1;
+            let value = self.expr()?;
+            if self.peek()? != ')' {
+                return None;
+            }
+            self.pos += 1;
+            return Some(value);
+        }
+        if ch.is_ascii_digit() {
+            let start = self.pos;
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.pos += 1;
+            }
+            return self.tokens[start..self.pos].iter().collect::<String>().parse().ok();
+        }
+
+        // variables may be written with or without `$`; unset ones count as 0
+        if ch == '$' {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c == '_' || c.is_ascii_alphanumeric()) {
+            self.pos += 1;
+        }
+        let name: String = self.tokens[start..self.pos].iter().collect();
+        if !is_name(&name) {
+            return None;
+        }
+        match (self.lookup)(&name) {
+            Some(value) => value.trim().parse().ok(),
+            None => Some(0),
+        }
+    }
+}
+
+// length of the longest `[A-Za-z_][A-Za-z0-9_]*` prefix of `text`
+pub(crate) fn name_prefix_len(text: &str) -> usize {
+    let mut len = 0;
+    for (i, ch) in text.char_indices() {
+        let ok = ch == '_' || ch.is_ascii_alphabetic() || (i > 0 && ch.is_ascii_digit());
+        if !ok {
+            break;
+        }
+        len = i + 1;
+    }
+    len
+}
+
+pub(crate) fn is_name(text: &str) -> bool {
+    !text.is_empty() && name_prefix_len(text) == text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/me".to_string()),
+            "ref" => Some("TARGET".to_string()),
+            "TARGET" => Some("hello".to_string()),
+            "N" => Some("4".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expand_plain_and_braced() {
+        assert_eq!(expand_value("$HOME/bin:${HOME}/.local", &lookup), "/home/me/bin:/home/me/.local");
+    }
+
+    #[test]
+    fn test_expand_indirect() {
+        assert_eq!(expand_value("${!ref}", &lookup), "hello");
+    }
+
+    #[test]
+    fn test_expand_unresolved_stays_literal() {
+        assert_eq!(expand_value("$NOPE ${NOPE} ${!NOPE} $ \\$HOME", &lookup), "$NOPE ${NOPE} ${!NOPE} $ $HOME");
+    }
+
+    #[test]
+    fn test_expand_arithmetic() {
+        assert_eq!(expand_value("$((1 + 2 * 3))", &lookup), "7");
+        assert_eq!(expand_value("x$(( (N - 1) * $N ))", &lookup), "x12");
+    }
+
+    #[test]
+    fn test_unbalanced_arithmetic_stays_literal() {
+        assert_eq!(expand_value("$((1+2", &lookup), "$((1+2");
+        assert_eq!(expand_value("$((1+2)", &lookup), "$((1+2)");
+        assert_eq!(expand_value("$((1/0))", &lookup), "$((1/0))");
+    }
+
+    #[test]
+    fn test_deeply_nested_arithmetic_stays_literal() {
+        let deep = format!("$(({}1{}))", "(".repeat(100_000), ")".repeat(100_000));
+        assert_eq!(expand_value(&deep, &lookup), deep);
+        let signs = format!("$(({}1))", "-".repeat(100_000));
+        assert_eq!(expand_value(&signs, &lookup), signs);
+        assert_eq!(expand_value("$(((((1)))))", &lookup), "1");
+    }
+
+    #[test]
+    fn test_expand_tracks_unresolved() {
+        let mut unresolved = Vec::new();
+        let out = expand_value_tracked("$HOME $NOPE $((1+2", &lookup, &mut unresolved, true);
+        assert_eq!(out, "/home/me $NOPE $((1+2");
+        assert_eq!(unresolved, vec!["$NOPE", "$(("]);
+
+        let mut unresolved = Vec::new();
+        let out = expand_value_tracked("$HOME:$NOPE:${NOPE}:${NOPE:-x}", &lookup, &mut unresolved, false);
+        assert_eq!(out, "/home/me:::x");
+        assert_eq!(unresolved, vec!["$NOPE", "${NOPE}"]);
+    }
+
+    #[test]
+    fn test_expand_defaults() {
+        assert_eq!(expand_value("${HOME:-/tmp}", &lookup), "/home/me");
+        assert_eq!(expand_value("${NOPE:-/tmp}", &lookup), "/tmp");
+        assert_eq!(expand_value("${NOPE:=$HOME/.config}", &lookup), "/home/me/.config");
+        assert_eq!(expand_value("${HOME:+set}${NOPE:+unset}", &lookup), "set");
+    }
+
+    #[test]
+    fn test_expand_default_with_quoted_dollar() {
+        assert_eq!(expand_value(r#"${PS1:-"$ "}"#, &lookup), "$ ");
+        assert_eq!(expand_value("${PS1:-'$HOME'}", &lookup), "$HOME");
+    }
+}
@@ -0,0 +1,7 @@
+pub mod encoding;
+pub mod expand;
+pub mod names;
+pub mod options;
+pub mod parser;
+pub mod render;
+mod word;
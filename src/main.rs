@@ -1,10 +1,21 @@
-use nu_plugin::{JsonSerializer, Plugin, PluginCommand, serve_plugin};
+use nu_plugin::{EvaluatedCall, JsonSerializer, Plugin, PluginCommand, serve_plugin};
+use std::path::PathBuf;
+
 use nu_protocol::{
-    Category, Example, LabeledError, PipelineData, Span, Type, Value,
+    record, Category, Example, LabeledError, PipelineData, Record, Span, Spanned, SyntaxShape, Type, Value,
 };
 
-mod parser;
-use parser::{parse_posix_exports, exports_to_nushell};
+use nu_plugin_from_posix::encoding::{decode, json_unescape};
+use nu_plugin_from_posix::names::is_valid_name;
+use nu_plugin_from_posix::options::{ErrorMode, Format, MergeMode, ParseOptions, Profile, RenderOptions};
+use nu_plugin_from_posix::parser::{
+    merge_with_env, parse_posix_report_lines, parse_posix_sources, Export, Skipped, Statement, Warning, INVALID_NAME,
+    UNTERMINATED_QUOTE,
+};
+use nu_plugin_from_posix::render::{
+    export_names, prefixed, render, render_statements, report_json, split_path, statements_to_posix, typed_literal,
+    Literal,
+};
 
 struct FromPosixPlugin;
 
@@ -14,24 +25,343 @@ impl Plugin for FromPosixPlugin {
     }
 
     fn commands(&self) -> Vec<Box<dyn PluginCommand<Plugin = Self>>> {
-        vec![Box::new(FromPosix)]
+        vec![
+            Box::new(FromPosix { name: "from posix" }),
+            Box::new(FromPosix { name: "from sh" }),
+            Box::new(FromPosix { name: "from bash" }),
+            Box::new(ToPosix),
+        ]
     }
 }
 
-struct FromPosix;
+// `from sh` and `from bash` are the same command under another name
+struct FromPosix {
+    name: &'static str,
+}
 
 impl PluginCommand for FromPosix {
     type Plugin = FromPosixPlugin;
 
     fn name(&self) -> &str {
-        "from posix"
+        self.name
     }
 
     fn signature(&self) -> nu_protocol::Signature {
-        nu_protocol::Signature::build("from posix")
+        nu_protocol::Signature::build(self.name)
             .input_output_types(vec![
                 (Type::String, Type::String),
+                (Type::Binary, Type::String),
+                (Type::Nothing, Type::String),
+                (Type::String, Type::Nothing),
+                (Type::String, Type::List(Box::new(Type::String))),
+                (
+                    Type::String,
+                    Type::Table(vec![("name".into(), Type::String), ("value".into(), Type::String)].into()),
+                ),
             ])
+            .optional("path", SyntaxShape::Filepath, "a file to convert instead of the piped input")
+            .named(
+                "strip-prefixes",
+                SyntaxShape::List(Box::new(SyntaxShape::String)),
+                "wrapper commands stripped before an export (default: sudo, time, command, exec, env)",
+                None,
+            )
+            .named(
+                "format",
+                SyntaxShape::String,
+                "output format: nushell (default), tsv for tab-separated name/value rows, json-env for a flat JSON object, or make for Makefile assignments",
+                None,
+            )
+            .named(
+                "target",
+                SyntaxShape::String,
+                "another name for --format",
+                None,
+            )
+            .named(
+                "quote-style",
+                SyntaxShape::String,
+                "how values are quoted: auto (default) or raw, which uses r#'...'# for values with backslashes",
+                None,
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "preset flags for common input: dotenv (--dotenv --trailing-newline), bashrc (--keep-comments --strip-prompts, following source from $HOME) or strict-lint (--strict --validate-only); explicit flags win",
+                None,
+            )
+            .named(
+                "comment-style",
+                SyntaxShape::String,
+                "how generated comments are marked: hash (# note, default) or slash (// note)",
+                None,
+            )
+            .switch(
+                "expand",
+                "expand $VAR, ${VAR} and ${!VAR} references using earlier exports and the current $env",
+                None,
+            )
+            .switch(
+                "expand-env",
+                "same as --expand",
+                None,
+            )
+            .switch(
+                "dotenv",
+                "also accept bare NAME=value assignments, as in .env files",
+                None,
+            )
+            .switch(
+                "no-export-keyword",
+                "same as --dotenv",
+                None,
+            )
+            .switch(
+                "only-exported",
+                "keep only assignments made with export, dropping bare ones",
+                None,
+            )
+            .switch(
+                "printf-q",
+                "decode printf %q style quoting, including bash's $'...' strings",
+                None,
+            )
+            .switch(
+                "windows-paths",
+                "keep backslashes in unquoted values literally, as in C:\\Users\\me",
+                None,
+            )
+            .switch(
+                "dedup",
+                "keep one assignment per variable: the last value, where the variable was first set",
+                None,
+            )
+            .switch(
+                "case-insensitive-dedup",
+                "fold names that differ only by case, like Path and PATH, keeping the last value",
+                None,
+            )
+            .named(
+                "dedup-case",
+                SyntaxShape::String,
+                "with --case-insensitive-dedup, the name kept: first (default, as first seen) or upper",
+                None,
+            )
+            .switch(
+                "strict-names",
+                "fail on names that aren't POSIX identifiers ([A-Za-z_][A-Za-z0-9_]*)",
+                None,
+            )
+            .named(
+                "allowed-name-chars",
+                SyntaxShape::String,
+                "other characters names may contain, e.g. '.' for java.home; counts with or without --strict-names",
+                None,
+            )
+            .switch(
+                "json-unescape",
+                "undo JSON string escaping (\\\", \\n, \\uXXXX) in the input before parsing it",
+                None,
+            )
+            .switch(
+                "interpolate",
+                "without --expand, turn $VAR and ${VAR} into $\"($env.VAR)\" interpolation; implies --subst",
+                None,
+            )
+            .switch(
+                "subst",
+                "turn $(command) and `command` substitutions into Nushell (command) subexpressions, run when the output is sourced",
+                None,
+            )
+            .switch(
+                "include-function-exports",
+                "also convert exports inside function bodies, which only run when the function is called",
+                None,
+            )
+            .switch(
+                "keep-comments",
+                "carry a comment ending a line over to the assignments made on it",
+                None,
+            )
+            .switch(
+                "percent-decode",
+                "decode %XX escapes in values, as in URL-encoded connection strings",
+                None,
+            )
+            .switch(
+                "transliterate-names",
+                "rewrite names into ASCII identifiers: accents dropped, other odd characters become _",
+                None,
+            )
+            .switch(
+                "skip-reserved",
+                "drop assignments to env vars Nushell manages itself instead of only warning",
+                None,
+            )
+            .switch(
+                "strip-prompts",
+                "skip PS1, PS2 and PROMPT_COMMAND, prompt settings Nushell doesn't use",
+                None,
+            )
+            .named(
+                "reserved-names",
+                SyntaxShape::List(Box::new(SyntaxShape::String)),
+                "env vars treated as Nushell-managed (default: PWD, FILE_PWD, CURRENT_FILE)",
+                None,
+            )
+            .named(
+                "encoding",
+                SyntaxShape::String,
+                "how binary input is decoded: auto (default, by byte order mark), utf8, utf16le or utf16be",
+                None,
+            )
+            .switch(
+                "aliases",
+                "also convert alias name=command lines to Nushell aliases, in their place among the exports",
+                None,
+            )
+            .named(
+                "env-file",
+                SyntaxShape::Filepath,
+                "also convert this file, after any piped input",
+                None,
+            )
+            .named(
+                "follow-source",
+                SyntaxShape::Directory,
+                "read files named by source FILE or . FILE, relative to this directory, and convert their exports in place",
+                None,
+            )
+            .switch(
+                "strict",
+                "fail on malformed input instead of skipping it",
+                None,
+            )
+            .switch(
+                "recover",
+                "convert what can be converted and list malformed input after the output",
+                None,
+            )
+            .switch(
+                "quiet",
+                "don't print warnings to stderr",
+                Some('q'),
+            )
+            .switch(
+                "source-labels",
+                "annotate each assignment with the index of the input item it came from",
+                None,
+            )
+            .switch(
+                "split-paths",
+                "render PATH-like variables as Nushell lists",
+                None,
+            )
+            .named(
+                "path-vars",
+                SyntaxShape::List(Box::new(SyntaxShape::String)),
+                "variables split by --split-paths (default: PATH, MANPATH, LD_LIBRARY_PATH)",
+                None,
+            )
+            .named(
+                "split-vars",
+                SyntaxShape::List(Box::new(SyntaxShape::String)),
+                "variables rendered as Nushell lists, split on --split-on",
+                None,
+            )
+            .named(
+                "split-on",
+                SyntaxShape::String,
+                "the character --split-vars splits on (default: ,)",
+                None,
+            )
+            .switch(
+                "deduplicate-paths",
+                "with --split-paths, drop repeated path segments, keeping the first",
+                None,
+            )
+            .switch(
+                "placeholders-as-null",
+                "render empty and placeholder values (your-key-here, changeme, <...>) as null",
+                None,
+            )
+            .switch(
+                "prompt-empty",
+                "render empty values as an (input ...) prompt asking for them when sourced",
+                None,
+            )
+            .switch(
+                "parse-ls-colors",
+                "render LS_COLORS as a record of file type or glob to style",
+                None,
+            )
+            .switch(
+                "annotate-types",
+                "append a comment with the inferred type of each value (string, int, path, list, ...)",
+                None,
+            )
+            .named(
+                "prefix",
+                SyntaxShape::String,
+                "put a prefix in front of every variable name, like APP_ to turn PORT into APP_PORT",
+                None,
+            )
+            .switch(
+                "typed",
+                "write unquoted numbers and true/false as Nushell ints, floats and bools instead of strings",
+                None,
+            )
+            .switch(
+                "trailing-newline",
+                "end the output with a newline, as a file would",
+                None,
+            )
+            .switch(
+                "validate-only",
+                "only check the input, reporting warnings and errors without any output",
+                None,
+            )
+            .switch(
+                "load-env",
+                "output one load-env { ... } record instead of an $env assignment per line",
+                None,
+            )
+            .switch(
+                "merge",
+                "set the variables in the calling scope instead of returning Nushell code",
+                None,
+            )
+            .switch(
+                "structured",
+                "return a table of name and value columns instead of Nushell code",
+                Some('s'),
+            )
+            .switch(
+                "keys-only",
+                "return just the list of variable names, each once, in order",
+                None,
+            )
+            .switch(
+                "report-json",
+                "output one JSON object with the exports, warnings, skipped input and counts",
+                None,
+            )
+            .switch(
+                "merge-with-current-env",
+                "start from the current $env and overlay the parsed exports",
+                None,
+            )
+            .named(
+                "merge-output",
+                SyntaxShape::String,
+                "with --merge-with-current-env: merged (default) for the full set, or overlay for only what changes",
+                None,
+            )
+            .switch(
+                "escape-for-interpolation",
+                "escape the whole output so it can be embedded in a $\"...\" string",
+                None,
+            )
             .category(Category::Formats)
     }
 
@@ -39,7 +369,15 @@ impl PluginCommand for FromPosix {
         "Convert POSIX export statements to Nushell $env assignments"
     }
 
-    fn examples(&self) -> Vec<Example> {
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["posix", "sh", "bash", "export", "env"]
+    }
+
+    fn examples(&self) -> Vec<Example<'_>> {
+        // the examples are written against the main name only
+        if self.name != "from posix" {
+            return vec![];
+        }
         vec![
             Example {
                 example: r#"'export FOO=bar' | from posix"#,
@@ -59,67 +397,992 @@ impl PluginCommand for FromPosix {
         ]
     }
 
+    fn run(
+        &self,
+        _plugin: &FromPosixPlugin,
+        engine: &nu_plugin::EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let cwd = || Ok(PathBuf::from(engine.get_current_dir()?));
+        let input_span = piped_span(call, &input)?;
+        let Some(sources) = collect_sources(call, input, &cwd)? else {
+            return Ok(PipelineData::Empty);
+        };
+
+        let current_env = || current_env(engine);
+        let output = convert(call, sources, input_span, &current_env, &mut std::io::stderr())?;
+
+        if call.has_flag("merge")? {
+            set_env_vars(output, &mut |name, value| Ok(engine.add_env_var(name, value)?))?;
+            return Ok(PipelineData::Empty);
+        }
+        Ok(PipelineData::Value(output, None))
+    }
+}
+
+// the inverse of `from posix`: a record of variables, or the table
+// `from posix -s` makes, as `export` lines
+struct ToPosix;
+
+impl PluginCommand for ToPosix {
+    type Plugin = FromPosixPlugin;
+
+    fn name(&self) -> &str {
+        "to posix"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        nu_protocol::Signature::build("to posix")
+            .input_output_types(vec![
+                (Type::record(), Type::String),
+                (
+                    Type::Table(vec![("name".into(), Type::String), ("value".into(), Type::String)].into()),
+                    Type::String,
+                ),
+            ])
+            .category(Category::Formats)
+    }
+
+    fn description(&self) -> &str {
+        "Convert a record of environment variables to POSIX shell export statements"
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["posix", "sh", "bash", "export", "env"]
+    }
+
+    fn examples(&self) -> Vec<Example<'_>> {
+        vec![
+            Example {
+                example: r#"{FOO: bar, MESSAGE: "hello world"} | to posix"#,
+                description: "Convert a record, quoting values the shell would split",
+                result: Some(Value::string("export FOO=bar\nexport MESSAGE='hello world'", Span::unknown())),
+            },
+            Example {
+                example: r#"open .env | from posix -s | to posix"#,
+                description: "Round-trip the table from `from posix --structured`",
+                result: None,
+            },
+        ]
+    }
+
     fn run(
         &self,
         _plugin: &FromPosixPlugin,
         _engine: &nu_plugin::EngineInterface,
-        call: &nu_plugin::EvaluatedCall,
+        call: &EvaluatedCall,
         input: PipelineData,
     ) -> Result<PipelineData, LabeledError> {
-        let span = call.head;
-
-        // get input as string
-        let input_string = match input {
-            PipelineData::Value(Value::String { val, .. }, _) => val,
-            PipelineData::ListStream(stream, _) => {
-                let values: Vec<Value> = stream.into_iter().collect();
-                if values.len() == 1 {
-                    if let Value::String { val, .. } = &values[0] {
-                        val.clone()
-                    } else {
-                        return Err(LabeledError::new("Input must be a string")
-                            .with_label("expected string input", span));
-                    }
-                } else {
-                    // join multiple string values with newlines
-                    values.into_iter()
-                        .filter_map(|v| match v {
-                            Value::String { val, .. } => Some(val),
-                            _ => None,
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n")
-                }
-            }
-            PipelineData::Value(Value::List { vals, .. }, _) => {
-                vals.into_iter()
-                    .filter_map(|v| match v {
-                        Value::String { val, .. } => Some(val),
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>()
-                    .join("\n")
+        let statements = exports_from_record(input.into_value(call.head)?)?;
+        Ok(PipelineData::Value(Value::string(statements_to_posix(&statements), call.head), None))
+    }
+}
+
+// the variables in a record, or in rows of `name` and `value`; lists like
+// `PATH` are joined with `:` and a null value leaves a bare `export NAME`
+fn exports_from_record(input: Value) -> Result<Vec<Statement>, LabeledError> {
+    let span = input.span();
+    let vars: Vec<(String, Value, Span)> = match input {
+        Value::Record { val, .. } => val.into_owned().into_iter().map(|(name, value)| (name, value, span)).collect(),
+        Value::List { vals, .. } => vals
+            .into_iter()
+            .map(|row| {
+                let row_span = row.span();
+                let record = row.into_record()?;
+                let name = record
+                    .get("name")
+                    .ok_or_else(|| LabeledError::new("Missing name column").with_label("each row needs a name", row_span))?
+                    .coerce_string()?;
+                let value = record.get("value").cloned().unwrap_or(Value::nothing(row_span));
+                Ok((name, value, row_span))
+            })
+            .collect::<Result<_, LabeledError>>()?,
+        other => {
+            return Err(LabeledError::new("Input must be a record")
+                .with_label(format!("expected a record or table, got {}", other.get_type()), span));
+        }
+    };
+
+    vars.into_iter()
+        .map(|(name, value, span)| {
+            if !is_valid_name(&name, "") {
+                return Err(LabeledError::new("Invalid variable name")
+                    .with_label(format!("{} is not a valid shell variable name", name), span));
             }
-            _ => {
+            let value = match value {
+                Value::Nothing { .. } => return Ok(Statement::Reexport { name }),
+                Value::List { vals, .. } => vals
+                    .iter()
+                    .map(|v| Ok(v.coerce_string()?))
+                    .collect::<Result<Vec<_>, LabeledError>>()?
+                    .join(":"),
+                other => other.coerce_string()?,
+            };
+            Ok(Statement::Set(Export::new(name, value)))
+        })
+        .collect()
+}
+
+// the text to convert, one string per pipeline item
+type Sources = Box<dyn Iterator<Item = String>>;
+
+// the pipeline items (or the file given as the `path` argument, which takes
+// their place) plus the --env-file; a stream is read as it's converted.
+// `None` when there's nothing at all
+fn collect_sources(
+    call: &EvaluatedCall,
+    input: PipelineData,
+    cwd: &dyn Fn() -> Result<PathBuf, LabeledError>,
+) -> Result<Option<Sources>, LabeledError> {
+    let span = call.head;
+    let input = match call.opt::<Spanned<String>>(0)? {
+        Some(path) => PipelineData::Value(Value::string(read_file(&path, cwd)?, path.span), None),
+        None => input,
+    };
+    let empty = matches!(input, PipelineData::Empty);
+
+    // get input as one string per pipeline item
+    let sources: Sources = match input {
+        PipelineData::Value(Value::String { val, .. }, _) => Box::new(std::iter::once(val)),
+        PipelineData::Value(Value::Binary { val, .. }, _) => Box::new(std::iter::once(decode_input(call, &val)?)),
+        PipelineData::ByteStream(stream, _) => Box::new(std::iter::once(decode_input(call, &stream.into_bytes()?)?)),
+        PipelineData::ListStream(stream, _) => {
+            // a lone item has to be a string, among several the others are
+            // passed over
+            let mut values = stream.into_iter().peekable();
+            let first = values.next();
+            if values.peek().is_none() && first.as_ref().is_some_and(|v| v.as_str().is_err()) {
                 return Err(LabeledError::new("Input must be a string")
                     .with_label("expected string input", span));
             }
+            Box::new(first.into_iter().chain(values).filter_map(|v| v.into_string().ok()))
+        }
+        PipelineData::Value(Value::List { vals, .. }, _) => {
+            Box::new(vals.into_iter().filter_map(|v| v.into_string().ok()))
+        }
+        PipelineData::Empty => Box::new(std::iter::empty()),
+        _ => {
+            return Err(LabeledError::new("Input must be a string")
+                .with_label("expected string input", span));
+        }
+    };
+
+    if let Some(file) = call.get_flag::<Spanned<String>>("env-file")? {
+        Ok(Some(Box::new(sources.chain(std::iter::once(read_file(&file, cwd)?)))))
+    } else if empty {
+        Ok(None)
+    } else {
+        Ok(Some(sources))
+    }
+}
+
+// where the piped-in text came from, for pointing errors into it; text read
+// from the `path` argument or --env-file has no span to point at
+fn piped_span(call: &EvaluatedCall, input: &PipelineData) -> Result<Option<Span>, LabeledError> {
+    if call.opt::<Value>(0)?.is_some() || call.get_flag::<Value>("env-file")?.is_some() {
+        return Ok(None);
+    }
+    Ok(input.span())
+}
+
+// the part of the input an error is about, when the input was a single
+// string literal whose span we know (quoted or not), otherwise the call;
+// `shape` is how many items the input had and how long the last one was
+fn error_span(error: &Skipped, shape: (usize, usize), input_span: Option<Span>, head: Span) -> Span {
+    let (Some(range), Some(input_span), (1, len)) = (&error.span, input_span, shape) else {
+        return head;
+    };
+    let offset = match (input_span.end - input_span.start).checked_sub(len) {
+        Some(0) => 0,
+        Some(2) => 1,
+        _ => return head,
+    };
+    let start = input_span.start + offset;
+    Span::new(start + range.start, start + range.end)
+}
+
+// read a file named on the command line, relative to the shell's directory
+fn read_file(
+    path: &Spanned<String>,
+    cwd: &dyn Fn() -> Result<PathBuf, LabeledError>,
+) -> Result<String, LabeledError> {
+    let full = match path.item.strip_prefix("~/") {
+        Some(rest) => std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default().join(rest),
+        None => cwd()?.join(&path.item),
+    };
+    std::fs::read_to_string(&full).map_err(|err| {
+        LabeledError::new("Could not read file").with_label(err.to_string(), path.span)
+    })
+}
+
+// bytes from `open --raw` and friends, decoded per --encoding
+fn decode_input(call: &EvaluatedCall, bytes: &[u8]) -> Result<String, LabeledError> {
+    let encoding = ParseOptions::from_call(call)?.encoding;
+    decode(bytes, encoding).map_err(|msg| {
+        LabeledError::new("Could not decode input").with_label(msg, call.head)
+    })
+}
+
+// the caller's environment as exports, sorted by name; non-string values are
+// coerced where Nushell can, and lists like `PATH` are joined with `:`
+fn current_env(engine: &nu_plugin::EngineInterface) -> Result<Vec<Export>, LabeledError> {
+    let mut vars: Vec<Export> = engine.get_env_vars()?
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let value = match value {
+                Value::List { vals, .. } => vals
+                    .iter()
+                    .map(|v| v.coerce_string().ok())
+                    .collect::<Option<Vec<_>>>()?
+                    .join(":"),
+                other => other.coerce_string().ok()?,
+            };
+            Some(Export::new(name, value))
+        })
+        .collect();
+    vars.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(vars)
+}
+
+// set the record of variables `convert` made for --merge through
+// `add_env_var`, which is the engine's outside of tests
+fn set_env_vars(
+    vars: Value,
+    add_env_var: &mut dyn FnMut(String, Value) -> Result<(), LabeledError>,
+) -> Result<(), LabeledError> {
+    for (name, value) in vars.into_record()? {
+        add_env_var(name, value)?;
+    }
+    Ok(())
+}
+
+// the engine-independent part of `from posix`: parse the input items,
+// report warnings to `stderr` and render the result; `current_env` is only
+// asked for with --merge-with-current-env, --expand and the bashrc
+// profile; `input_span` is where the piped-in text came from, for pointing
+// errors at the offending part
+fn convert<S: AsRef<str>>(
+    call: &EvaluatedCall,
+    sources: impl IntoIterator<Item = S>,
+    input_span: Option<Span>,
+    current_env: &dyn Fn() -> Result<Vec<Export>, LabeledError>,
+    stderr: &mut dyn std::io::Write,
+) -> Result<Value, LabeledError> {
+    let span = call.head;
+    let mut options = ParseOptions::from_call(call)?;
+    if options.expand {
+        let env = current_env()?.into_iter().map(|var| (var.name, var.value)).collect();
+        options.ambient_env = Some(env);
+    }
+    if options.follow_source.is_none() && Profile::from_call(call)? == Some(Profile::Bashrc) {
+        options.follow_source = current_env()?.into_iter().find(|var| var.name == "HOME").map(|home| PathBuf::from(home.value));
+    }
+    let render_options = RenderOptions::from_call(call)?;
+
+    // --merge hands back no output, so flags choosing one make no sense with it
+    if call.has_flag("merge")? {
+        for other in ["structured", "keys-only", "report-json", "load-env", "validate-only"] {
+            if call.has_flag(other)? {
+                let span = call.get_flag_span(other).unwrap_or(span);
+                return Err(LabeledError::new("Conflicting flags")
+                    .with_label(format!("--{} can't be combined with --merge", other), span));
+            }
+        }
+    }
+
+    // the items are taken as they come, noting what `error_span` needs and
+    // the first one that couldn't be unescaped
+    let unescape = call.has_flag("json-unescape")?;
+    let mut shape = (0, 0);
+    let mut unescape_error = None;
+    let texts = sources.into_iter().map(|source| {
+        let text = match (unescape, source.as_ref()) {
+            (true, source) => json_unescape(source).unwrap_or_else(|msg| {
+                unescape_error.get_or_insert(msg);
+                String::new()
+            }),
+            (false, source) => source.to_string(),
         };
+        shape = (shape.0 + 1, text.len());
+        text
+    });
 
-        // parse POSIX exports
-        let exports = parse_posix_exports(&input_string);
+    // parse POSIX exports, keeping track of which item each came from
+    // when labels were asked for; otherwise each item is more lines of one
+    // input
+    let report = if render_options.source_labels {
+        parse_posix_sources(texts, &options)
+    } else {
+        let lines = texts.flat_map(|text| text.split_inclusive('\n').map(str::to_string).collect::<Vec<_>>());
+        parse_posix_report_lines(lines, &options)
+    };
+    if let Some(msg) = unescape_error {
+        return Err(LabeledError::new("Could not unescape input").with_label(msg, span));
+    }
+
+    if options.strict_names {
+        if let Some(error) = report.errors().find(|error| error.reason == INVALID_NAME) {
+            let label_span = error_span(error, shape, input_span, span);
+            return Err(LabeledError::new("Invalid variable name").with_label(error.to_string(), label_span));
+        }
+    }
+
+    if options.errors == ErrorMode::Strict {
+        if let Some(error) = report.errors().next() {
+            let label_span = error_span(error, shape, input_span, span);
+            return Err(LabeledError::new("Malformed input").with_label(error.to_string(), label_span));
+        }
+    }
+
+    // a quote that never closes means the input isn't what it seems, so
+    // only --recover carries on past it
+    if options.errors == ErrorMode::Lenient {
+        if let Some(error) = report.errors().find(|error| error.reason == UNTERMINATED_QUOTE) {
+            let label_span = error_span(error, shape, input_span, span);
+            return Err(LabeledError::new("Unterminated quote").with_label(error.to_string(), label_span));
+        }
+    }
+
+    // the report carries its own warnings
+    if render_options.report_json {
+        return Ok(Value::string(report_json(&report), span));
+    }
+
+    if !call.has_flag("quiet")? {
+        emit_warnings(&report.warnings, stderr);
+        // an overlay lists values and --merge sets them, so neither can take
+        // a variable out of the environment
+        let dropped_by = if call.has_flag("merge")? {
+            Some("--merge")
+        } else if render_options.merge == Some(MergeMode::Overlay) {
+            Some("an overlay")
+        } else {
+            None
+        };
+        if let Some(how) = dropped_by {
+            for statement in &report.statements {
+                if let Statement::Unset(name) = statement {
+                    let _ = writeln!(stderr, "from posix: warning: unset {} is dropped, {} can't remove a variable", name, how);
+                }
+            }
+        }
+    }
+    if render_options.validate_only {
+        return Ok(Value::nothing(span));
+    }
+
+    if call.has_flag("keys-only")? {
+        let names = export_names(&report.exports());
+        let names = names.into_iter().map(|name| Value::string(prefixed(&name, &render_options), span)).collect();
+        return Ok(Value::list(names, span));
+    }
+
+    // the variables for `run` to set, path lists split when asked
+    if call.has_flag("merge")? {
+        let vars: Record = report
+            .into_exports()
+            .into_iter()
+            .map(|export| {
+                let value = if render_options.split_paths && render_options.path_vars.contains(&export.name) {
+                    let segments = split_path(&export.value, render_options.deduplicate_paths);
+                    Value::list(segments.into_iter().map(|segment| Value::string(segment, span)).collect(), span)
+                } else {
+                    export_value(&export, render_options.typed, span)
+                };
+                (prefixed(&export.name, &render_options), value)
+            })
+            .collect();
+        return Ok(Value::record(vars, span));
+    }
 
-        // convert to Nushell format
-        let nushell_output = exports_to_nushell(exports);
+    // a bare `export NAME` has no value of its own
+    if call.has_flag("structured")? {
+        let rows = report
+            .statements
+            .into_iter()
+            .filter_map(|statement| match statement {
+                Statement::Set(export) => Some((export.name.clone(), export_value(&export, render_options.typed, span))),
+                Statement::Reexport { name } => Some((name, Value::nothing(span))),
+                Statement::Alias { .. } | Statement::Unset(_) => None,
+            })
+            .map(|(name, value)| {
+                let name = Value::string(prefixed(&name, &render_options), span);
+                Value::record(record! { "name" => name, "value" => value }, span)
+            })
+            .collect();
+        return Ok(Value::list(rows, span));
+    }
+
+    // with --recover, what broke is listed after Nushell output as comments,
+    // and on stderr for formats that have no room for them
+    let errors: Vec<String> = match options.errors {
+        ErrorMode::Recover => report.errors().map(|error| error.to_string()).collect(),
+        _ => Vec::new(),
+    };
+
+    // convert to Nushell format; a merged environment is only variables
+    let mut output = match render_options.merge {
+        Some(mode) => {
+            let exports = merge_with_env(current_env()?, report.statements, mode);
+            render(exports, &render_options)
+        }
+        None => render_statements(report.statements, &render_options),
+    };
+
+    for error in errors {
+        if render_options.format == Format::Nushell {
+            output.push_str(&format!("\n{} error: {}", render_options.comment_style.marker(), error));
+        } else {
+            let _ = writeln!(stderr, "from posix: error: {}", error);
+        }
+    }
+    Ok(Value::string(output, span))
+}
 
-        // return as string value
-        Ok(PipelineData::Value(
-            Value::string(nushell_output, span),
-            None,
-        ))
+// an export's value, as a number or bool with --typed when it plainly is one
+fn export_value(export: &Export, typed: bool, span: Span) -> Value {
+    match typed_literal(export).filter(|_| typed) {
+        Some(Literal::Int(int)) => Value::int(int, span),
+        Some(Literal::Float(float)) => Value::float(float, span),
+        Some(Literal::Bool(bool)) => Value::bool(bool, span),
+        None => Value::string(export.value.clone(), span),
+    }
+}
+
+// warnings go to the plugin's stderr, which Nushell shows to the user
+// without touching the pipeline output
+fn emit_warnings(warnings: &[Warning], out: &mut dyn std::io::Write) {
+    for warning in warnings {
+        let _ = writeln!(out, "from posix: warning: {}", warning);
     }
 }
 
 fn main() {
     serve_plugin(&FromPosixPlugin, JsonSerializer {})
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flag(name: &str) -> Spanned<String> {
+        Spanned { item: name.to_string(), span: Span::test_data() }
+    }
+
+    // a stand-in for the engine's `$env`
+    fn mock_env() -> Result<Vec<Export>, LabeledError> {
+        Ok(vec![Export::new("HOME", "/home/me")])
+    }
+
+    // `collect_sources`, read to the end
+    fn collected(
+        call: &EvaluatedCall,
+        input: PipelineData,
+        cwd: &dyn Fn() -> Result<PathBuf, LabeledError>,
+    ) -> Result<Option<Vec<String>>, LabeledError> {
+        Ok(collect_sources(call, input, cwd)?.map(Iterator::collect))
+    }
+
+    // run a call against a fake stderr, returning (output, stderr)
+    fn convert_str(call: &EvaluatedCall, input: &str) -> (String, String) {
+        let mut stderr = Vec::new();
+        let output = convert(call, &[input.to_string()], None, &mock_env, &mut stderr).unwrap();
+        (output.into_string().unwrap(), String::from_utf8(stderr).unwrap())
+    }
+
+    #[test]
+    fn test_aliased_command_names() {
+        let commands = FromPosixPlugin.commands();
+        let names: Vec<_> = commands.iter().map(|c| c.name().to_string()).collect();
+        assert_eq!(names, vec!["from posix", "from sh", "from bash", "to posix"]);
+
+        let posix = commands[0].signature();
+        let bash = commands.iter().find(|c| c.name() == "from bash").unwrap().signature();
+        assert_eq!(bash.name, "from bash");
+        assert_eq!(bash.named.len(), posix.named.len());
+        assert_eq!(bash.input_output_types, posix.input_output_types);
+    }
+
+    #[test]
+    fn test_warnings_emitted() {
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("expand"));
+        let (output, stderr) = convert_str(&call, "export A=$MISSING");
+        assert_eq!(output, "$env.A = \"\"");
+        assert_eq!(stderr, "from posix: warning: line 1: could not expand $MISSING in A, left empty\n");
+    }
+
+    #[test]
+    fn test_quiet_suppresses_warnings() {
+        let call = EvaluatedCall::new(Span::test_data())
+            .with_flag(flag("expand"))
+            .with_flag(flag("quiet"));
+        let (output, stderr) = convert_str(&call, "export A=$MISSING");
+        assert_eq!(output, "$env.A = \"\"");
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn test_merge_with_current_env() {
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("merge-with-current-env"));
+        let (output, _) = convert_str(&call, "export FOO=bar");
+        assert_eq!(output, "$env.HOME = /home/me\n$env.FOO = bar");
+
+        // an unset takes the variable out of the snapshot
+        let (output, stderr) = convert_str(&call, "export FOO=bar\nunset HOME");
+        assert_eq!(output, "$env.FOO = bar");
+        assert_eq!(stderr, "");
+
+        let call = call.with_named(flag("merge-output"), Value::test_string("overlay"));
+        let (output, _) = convert_str(&call, "export HOME=/home/me FOO=bar");
+        assert_eq!(output, "$env.FOO = bar");
+
+        // neither an overlay nor --merge has a way to remove a variable
+        let (output, stderr) = convert_str(&call, "export FOO=bar\nunset HOME");
+        assert_eq!(output, "$env.FOO = bar");
+        assert_eq!(stderr, "from posix: warning: unset HOME is dropped, an overlay can't remove a variable\n");
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("merge"));
+        let mut stderr = Vec::new();
+        let output = convert(&call, &["export FOO=bar\nunset HOME".to_string()], None, &mock_env, &mut stderr).unwrap();
+        assert_eq!(output.into_record().unwrap().columns().collect::<Vec<_>>(), vec!["FOO"]);
+        assert_eq!(String::from_utf8(stderr).unwrap(), "from posix: warning: unset HOME is dropped, --merge can't remove a variable\n");
+    }
+
+    #[test]
+    fn test_report_json() {
+        let call = EvaluatedCall::new(Span::test_data())
+            .with_flag(flag("report-json"))
+            .with_flag(flag("expand"));
+        let (output, stderr) = convert_str(&call, "export A=$MISSING\nexport =oops");
+        assert_eq!(stderr, "");
+        assert!(output.starts_with("{\"exports\":["));
+        assert!(output.contains("\"warnings\":[{\"line\":1,"));
+        assert!(output.contains("\"skipped\":[{\"line\":2,\"text\":\"=oops\",\"reason\":\"empty variable name\",\"malformed\":true}]"));
+        assert!(output.ends_with("\"stats\":{\"lines\":2,\"exports\":1,\"warnings\":1,\"skipped\":1}}"));
+    }
+
+    #[test]
+    fn test_aliases_with_exports() {
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("aliases"));
+        let profile = "export EDITOR=vim\n# listing\nalias ll='ls -l' la=\"ls -a\"\nexport PAGER=less";
+        let (output, _) = convert_str(&call, profile);
+        assert_eq!(output, "$env.EDITOR = vim\nalias ll = ls -l\nalias la = ls -a\n$env.PAGER = less");
+
+        let (output, _) = convert_str(&EvaluatedCall::new(Span::test_data()), profile);
+        assert_eq!(output, "$env.EDITOR = vim\n$env.PAGER = less");
+    }
+
+    #[test]
+    fn test_recover_keeps_good_lines_and_reports_errors() {
+        let input = "export GOOD=1\nexport =oops";
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("recover"));
+        let (output, _) = convert_str(&call, input);
+        assert_eq!(output, "$env.GOOD = \"1\"\n# error: line 2: =oops: empty variable name");
+
+        let slash = call.clone().with_named(flag("comment-style"), Value::test_string("slash"));
+        let (output, _) = convert_str(&slash.with_flag(flag("keep-comments")), "export A=1 # why\nexport =oops");
+        assert_eq!(output, "$env.A = \"1\" // why\n// error: line 2: =oops: empty variable name");
+
+        let call = call.with_named(flag("format"), Value::test_string("tsv"));
+        let (output, stderr) = convert_str(&call, input);
+        assert_eq!(output, "GOOD\t1");
+        assert_eq!(stderr, "from posix: error: line 2: =oops: empty variable name\n");
+
+        let (output, _) = convert_str(&EvaluatedCall::new(Span::test_data()), input);
+        assert_eq!(output, "$env.GOOD = \"1\"");
+    }
+
+    #[test]
+    fn test_strict_fails_on_malformed_input() {
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("strict"));
+        let mut stderr = Vec::new();
+        let error = convert(&call, &["export GOOD=1\nexport =oops".to_string()], None, &mock_env, &mut stderr).unwrap_err();
+        assert_eq!(error.msg, "Malformed input");
+        assert_eq!(error.labels[0].text, "line 2: =oops: empty variable name");
+        assert_eq!(error.labels[0].span, Span::test_data());
+        assert!(convert(&call, &["export GOOD=1".to_string()], None, &mock_env, &mut stderr).is_ok());
+    }
+
+    #[test]
+    fn test_unterminated_quote_fails_unless_recovering() {
+        let input = "export A=1\nexport X=\"oops";
+        let call = EvaluatedCall::new(Span::test_data());
+        let mut stderr = Vec::new();
+        let error = convert(&call, &[input.to_string()], None, &mock_env, &mut stderr).unwrap_err();
+        assert_eq!(error.msg, "Unterminated quote");
+        assert_eq!(error.labels[0].text, "line 2: X=\"oops: unterminated quote");
+
+        let (output, _) = convert_str(&call.with_flag(flag("recover")), input);
+        assert_eq!(output, "$env.A = \"1\"\n# error: line 2: X=\"oops: unterminated quote");
+    }
+
+    #[test]
+    fn test_error_span_points_into_input() {
+        // as if typed as `"export A=1\nexport B=\"oops" | from posix --strict`
+        let input = "export A=1\nexport B=\"oops";
+        let input_span = Span::new(100, 100 + input.len() + 2);
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("strict"));
+        let mut stderr = Vec::new();
+        let error = convert(&call, &[input.to_string()], Some(input_span), &mock_env, &mut stderr).unwrap_err();
+        assert_eq!(error.labels[0].text, "line 2: B=\"oops: unterminated quote");
+        // the quote opening the literal is at 100, so `B="oops` starts 19 bytes in
+        assert_eq!(error.labels[0].span, Span::new(119, 126));
+
+        // a span that doesn't line up with the text falls back to the call
+        let error = convert(&call, &[input.to_string()], Some(Span::new(0, 3)), &mock_env, &mut stderr).unwrap_err();
+        assert_eq!(error.labels[0].span, Span::test_data());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let input = "export PS1='\\u$ '\nalias ll='ls -l'\nexport EDITOR=vim # editor";
+        let call = EvaluatedCall::new(Span::test_data()).with_named(flag("profile"), Value::test_string("bashrc"));
+        let (output, stderr) = convert_str(&call, input);
+        assert_eq!(output, "$env.EDITOR = vim # editor");
+        assert_eq!(stderr, "from posix: warning: line 1: PS1 is a shell prompt setting Nushell doesn't use, skipped\n");
+
+        // Nushell's own variables are only warned about, as without a profile
+        let (output, stderr) = convert_str(&call, "export PWD=/tmp");
+        assert_eq!(output, "$env.PWD = /tmp");
+        assert_eq!(stderr, "from posix: warning: line 1: PWD is managed by Nushell, assigning it may break Nushell\n");
+
+        // an explicit flag wins over the profile
+        let (output, _) = convert_str(&call.clone().with_named(flag("keep-comments"), Value::test_bool(false)), input);
+        assert_eq!(output, "$env.EDITOR = vim");
+
+        // `source` is followed from the user's $env.HOME
+        let home = std::env::temp_dir().join(format!("from-posix-home-{}", std::process::id()));
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::write(home.join(".aliases"), "export FROM_HOME=1").unwrap();
+        let engine_env = || Ok(vec![Export::new("HOME", home.to_string_lossy())]);
+        let mut stderr = Vec::new();
+        let output = convert(&call, &["source .aliases".to_string()], None, &engine_env, &mut stderr).unwrap();
+        assert_eq!(output.as_str().unwrap(), "$env.FROM_HOME = \"1\"");
+        std::fs::remove_dir_all(&home).unwrap();
+
+        let call = EvaluatedCall::new(Span::test_data()).with_named(flag("profile"), Value::test_string("dotenv"));
+        let (output, _) = convert_str(&call, "NAME=app\nexport PORT=80");
+        assert_eq!(output, "$env.NAME = app\n$env.PORT = \"80\"\n");
+
+        let call = EvaluatedCall::new(Span::test_data()).with_named(flag("profile"), Value::test_string("strict-lint"));
+        let mut stderr = Vec::new();
+        // names are only checked strictly with --strict-names given too
+        let output = convert(&call, &["export java.home=/opt".to_string()], None, &mock_env, &mut stderr).unwrap();
+        assert!(output.is_nothing());
+        let strict_names = call.clone().with_flag(flag("strict-names"));
+        let error = convert(&strict_names, &["export java.home=/opt".to_string()], None, &mock_env, &mut stderr).unwrap_err();
+        assert_eq!(error.msg, "Invalid variable name");
+        let error = convert(&call, &["export =oops".to_string()], None, &mock_env, &mut stderr).unwrap_err();
+        assert_eq!(error.msg, "Malformed input");
+        // valid input passes with nothing but its warnings
+        let output = convert(&strict_names, &["export A=1 A-B=2".to_string()], None, &mock_env, &mut stderr);
+        assert_eq!(output.unwrap_err().msg, "Invalid variable name");
+        let mut stderr = Vec::new();
+        let output = convert(&call, &["export A=1\nexport PWD=/x".to_string()], None, &mock_env, &mut stderr).unwrap();
+        assert!(output.is_nothing());
+        assert_eq!(String::from_utf8(stderr).unwrap().lines().count(), 1);
+        let call = call.with_named(flag("validate-only"), Value::test_bool(false));
+        let (output, _) = convert_str(&call.with_flag(flag("recover")), "export A=1\nexport =oops");
+        assert_eq!(output, "$env.A = \"1\"\n# error: line 2: =oops: empty variable name");
+        let mut stderr = Vec::new();
+
+        let call = EvaluatedCall::new(Span::test_data()).with_named(flag("profile"), Value::test_string("zshrc"));
+        let error = convert(&call, &[input.to_string()], None, &mock_env, &mut stderr).unwrap_err();
+        assert_eq!(error.msg, "Invalid profile");
+    }
+
+    #[test]
+    fn test_strict_names() {
+        let input = "export java.home=/opt/jdk";
+        let mut stderr = Vec::new();
+
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("strict-names"));
+        let error = convert(&call, &[input.to_string()], None, &mock_env, &mut stderr).unwrap_err();
+        assert_eq!(error.msg, "Invalid variable name");
+        assert_eq!(error.labels[0].text, "line 1: java.home: invalid variable name");
+
+        let call = call.with_named(flag("allowed-name-chars"), Value::test_string("."));
+        let (output, _) = convert_str(&call, input);
+        assert_eq!(output, "$env.\"java.home\" = /opt/jdk");
+
+        // without --strict-names the name is skipped with a warning, unless
+        // its characters are allowed
+        let call = EvaluatedCall::new(Span::test_data());
+        let (output, stderr) = convert_str(&call, input);
+        assert_eq!(output, "");
+        assert_eq!(stderr, "from posix: warning: line 1: java.home is not a valid variable name, skipped\n");
+        let call = call.with_named(flag("allowed-name-chars"), Value::test_string("."));
+        let (output, stderr) = convert_str(&call, input);
+        assert_eq!(output, "$env.\"java.home\" = /opt/jdk");
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn test_strict_expansion_fails_on_undefined_variables() {
+        let input = "export A=1\nexport B=\"$A:$UNDEFINED\"";
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("expand")).with_flag(flag("strict"));
+        let mut stderr = Vec::new();
+        let error = convert(&call, &[input.to_string()], None, &mock_env, &mut stderr).unwrap_err();
+        assert_eq!(error.labels[0].text, "line 2: $UNDEFINED: undefined variable in B");
+
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("expand"));
+        let (output, _) = convert_str(&call, input);
+        assert_eq!(output, "$env.A = \"1\"\n$env.B = \"1:\"");
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let cwd = || Ok(std::env::temp_dir());
+        let call = EvaluatedCall::new(Span::test_data());
+        assert_eq!(collected(&call, PipelineData::Empty, &cwd).unwrap(), None);
+
+        let name = format!("from-posix-env-file-{}", std::process::id());
+        std::fs::write(std::env::temp_dir().join(&name), "export FROM_FILE=1").unwrap();
+        let call = call.with_named(flag("env-file"), Value::test_string(&name));
+        let sources = collected(&call, PipelineData::Empty, &cwd).unwrap();
+        assert_eq!(sources, Some(vec!["export FROM_FILE=1".to_string()]));
+
+        let piped = PipelineData::Value(Value::test_string("export PIPED=1"), None);
+        let sources = collected(&call, piped, &cwd).unwrap();
+        assert_eq!(sources, Some(vec!["export PIPED=1".to_string(), "export FROM_FILE=1".to_string()]));
+        std::fs::remove_file(std::env::temp_dir().join(&name)).unwrap();
+
+        let call = EvaluatedCall::new(Span::test_data()).with_named(flag("env-file"), Value::test_string("no/such/file"));
+        assert_eq!(collected(&call, PipelineData::Empty, &cwd).unwrap_err().msg, "Could not read file");
+    }
+
+    #[test]
+    fn test_path_argument_replaces_piped_input() {
+        let cwd = || Ok(std::env::temp_dir());
+        let name = format!("from-posix-path-arg-{}", std::process::id());
+        std::fs::write(std::env::temp_dir().join(&name), "export FROM_PATH=1").unwrap();
+
+        let call = EvaluatedCall::new(Span::test_data()).with_positional(Value::test_string(&name));
+        let sources = collected(&call, PipelineData::Empty, &cwd).unwrap();
+        assert_eq!(sources, Some(vec!["export FROM_PATH=1".to_string()]));
+        let piped = PipelineData::Value(Value::test_string("export PIPED=1"), None);
+        let sources = collected(&call, piped, &cwd).unwrap();
+        assert_eq!(sources, Some(vec!["export FROM_PATH=1".to_string()]));
+
+        // without it, the pipeline is read as before
+        let piped = PipelineData::Value(Value::test_string("export PIPED=1"), None);
+        let sources = collected(&EvaluatedCall::new(Span::test_data()), piped, &cwd).unwrap();
+        assert_eq!(sources, Some(vec!["export PIPED=1".to_string()]));
+        std::fs::remove_file(std::env::temp_dir().join(&name)).unwrap();
+
+        // error labels only point into text that was piped in
+        let piped = PipelineData::Value(Value::string("export =oops", Span::new(40, 52)), None);
+        assert_eq!(piped_span(&EvaluatedCall::new(Span::test_data()), &piped).unwrap(), Some(Span::new(40, 52)));
+        assert_eq!(piped_span(&call, &piped).unwrap(), None);
+        let env_file = EvaluatedCall::new(Span::test_data()).with_named(flag("env-file"), Value::test_string(".env"));
+        assert_eq!(piped_span(&env_file, &piped).unwrap(), None);
+
+        let missing = Value::string("no/such/file", Span::new(5, 17));
+        let call = EvaluatedCall::new(Span::test_data()).with_positional(missing);
+        let error = collected(&call, PipelineData::Empty, &cwd).unwrap_err();
+        assert_eq!(error.msg, "Could not read file");
+        assert_eq!(error.labels[0].span, Span::new(5, 17));
+    }
+
+    #[test]
+    fn test_expand_uses_engine_env() {
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("expand"));
+        let input = "export CONFIG=${HOME}/.config BIN=\"$HOME/bin\" OTHER=$UNSET LITERAL='$HOME'";
+        let (output, stderr) = convert_str(&call, input);
+        assert_eq!(
+            output,
+            "$env.CONFIG = /home/me/.config\n$env.BIN = /home/me/bin\n$env.OTHER = \"\"\n$env.LITERAL = '$HOME'"
+        );
+        assert_eq!(stderr, "from posix: warning: line 1: could not expand $UNSET in OTHER, left empty\n");
+
+        // --expand-env is kept as another name for it
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("expand-env"));
+        assert_eq!(convert_str(&call, input).0, output);
+    }
+
+    #[test]
+    fn test_command_substitution_is_commented_out_without_subst() {
+        for call in [
+            EvaluatedCall::new(Span::test_data()),
+            EvaluatedCall::new(Span::test_data()).with_flag(flag("expand")),
+        ] {
+            let (output, stderr) = convert_str(&call, "export X=$(date) Y=\"$(date +%s)\" Z=`hostname -f`");
+            assert_eq!(output, "# $env.X = '$(date)'\n# $env.Y = '$(date +%s)'\n# $env.Z = \"`hostname -f`\"");
+            assert_eq!(
+                stderr.lines().next(),
+                Some("from posix: warning: line 1: X runs a command substitution, commented out for converting by hand")
+            );
+        }
+    }
+
+    #[test]
+    fn test_default_expansions() {
+        let input = "export EMPTY=\n\
+                     export SET=${HOME:-/tmp}\n\
+                     export UNSET=${NOPE:-/tmp}\n\
+                     export BLANK=${EMPTY:-x} KEPT=${EMPTY-x}\n\
+                     export XDG_CONFIG_HOME=${XDG_CONFIG_HOME:=$HOME/.config}";
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("expand"));
+        let (output, _) = convert_str(&call, input);
+        assert_eq!(
+            output,
+            "$env.EMPTY = \"\"\n$env.SET = /home/me\n$env.UNSET = /tmp\n$env.BLANK = x\n$env.KEPT = \"\"\n\
+             $env.XDG_CONFIG_HOME = /home/me/.config"
+        );
+
+        // left for Nushell to decide when the output is sourced
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("interpolate"));
+        let (output, _) = convert_str(&call, "export XDG_CONFIG_HOME=${XDG_CONFIG_HOME:-$HOME/.config}\nexport U=${U-'a b'}");
+        assert_eq!(
+            output,
+            "$env.XDG_CONFIG_HOME = (if ($env.XDG_CONFIG_HOME? | is-empty) { $\"($env.HOME)/.config\" } else { $env.XDG_CONFIG_HOME })\n\
+             $env.U = ($env.U? | default \"a b\")"
+        );
+    }
+
+    #[test]
+    fn test_interpolate_renders_command_substitutions() {
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("interpolate"));
+        let input = "export NOW=$(date)\nexport DIR=$(dirname $(pwd))\nexport HOST=`hostname -f`\nexport AT=\"`uname` on $HOST\"";
+        let (output, stderr) = convert_str(&call, input);
+        assert_eq!(
+            output,
+            "$env.NOW = (date)\n$env.DIR = (dirname (pwd))\n$env.HOST = (hostname -f)\n$env.AT = $\"(uname) on ($env.HOST)\""
+        );
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn test_subst_renders_subexpressions() {
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("subst"));
+        let input = "export A=$(date) B=\"x\" C=\"built $(date +%F) by $USER\" D='$(date)'";
+        let (output, _) = convert_str(&call, input);
+        assert_eq!(output, "$env.A = (date)\n$env.B = x\n$env.C = $\"built (date +%F) by $USER\"\n$env.D = '$(date)'");
+    }
+
+    #[test]
+    fn test_export_without_value() {
+        let call = EvaluatedCall::new(Span::test_data());
+        assert_eq!(convert_str(&call, "export FOO").0, "$env.FOO = $env.FOO");
+        assert_eq!(convert_str(&call, "export FOO BAR=1").0, "$env.FOO = $env.FOO\n$env.BAR = \"1\"");
+
+        let call = call.with_flag(flag("structured"));
+        let mut stderr = Vec::new();
+        let rows = convert(&call, &["export FOO BAR=1".to_string()], None, &mock_env, &mut stderr).unwrap().into_list().unwrap();
+        let values: Vec<_> = rows.iter().map(|row| row.as_record().unwrap().get("value").unwrap().clone()).collect();
+        assert!(values[0].is_nothing());
+        assert_eq!(values[1].as_str().unwrap(), "1");
+    }
+
+    #[test]
+    fn test_keys_only() {
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("keys-only"));
+        let mut stderr = Vec::new();
+        let input = "export B=1 A=2\nexport B=3 C=4".to_string();
+        let names = convert(&call, &[input], None, &mock_env, &mut stderr).unwrap();
+        let names: Vec<_> = names.into_list().unwrap().into_iter().map(|v| v.into_string().unwrap()).collect();
+        assert_eq!(names, vec!["B", "A", "C"]);
+    }
+
+    #[test]
+    fn test_merge_sets_variables() {
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("merge")).with_flag(flag("split-paths"));
+        let mut stderr = Vec::new();
+        let input = "export EDITOR=vim\nexport PATH=/usr/bin:/bin\nunset OLD".to_string();
+        let vars = convert(&call, &[input], None, &mock_env, &mut stderr).unwrap();
+
+        // a stand-in for the engine's add_env_var
+        let mut added = Vec::new();
+        set_env_vars(vars, &mut |name, value| {
+            added.push((name, value));
+            Ok(())
+        })
+        .unwrap();
+        let span = Span::test_data();
+        assert_eq!(added, vec![
+            ("EDITOR".to_string(), Value::string("vim", span)),
+            ("PATH".to_string(), Value::list(vec![Value::string("/usr/bin", span), Value::string("/bin", span)], span)),
+        ]);
+
+        let call = call.with_flag(flag("structured"));
+        let error = convert(&call, &["export A=1".to_string()], None, &mock_env, &mut stderr).unwrap_err();
+        assert_eq!(error.msg, "Conflicting flags");
+        assert_eq!(error.labels[0].text, "--structured can't be combined with --merge");
+    }
+
+    #[test]
+    fn test_structured() {
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("structured"));
+        let mut stderr = Vec::new();
+        let input = "export PATH=/usr/bin EDITOR='vim -u NONE'\nexport PAGER=less".to_string();
+        let rows = convert(&call, &[input], None, &mock_env, &mut stderr).unwrap().into_list().unwrap();
+        let rows: Vec<_> = rows
+            .into_iter()
+            .map(|row| {
+                let row = row.into_record().unwrap();
+                assert_eq!(row.columns().collect::<Vec<_>>(), vec!["name", "value"]);
+                (row.get("name").unwrap().as_str().unwrap().to_string(), row.get("value").unwrap().as_str().unwrap().to_string())
+            })
+            .collect();
+        assert_eq!(rows, vec![
+            ("PATH".to_string(), "/usr/bin".to_string()),
+            ("EDITOR".to_string(), "vim -u NONE".to_string()),
+            ("PAGER".to_string(), "less".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_json_unescape_input() {
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("json-unescape"));
+        let (output, _) = convert_str(&call, r#"export FOO=\"bar baz\"\nexport N=1"#);
+        assert_eq!(output, "$env.FOO = \"bar baz\"\n$env.N = \"1\"");
+
+        let mut stderr = Vec::new();
+        let error = convert(&call, &[r"export A=\x".to_string()], None, &mock_env, &mut stderr).unwrap_err();
+        assert_eq!(error.msg, "Could not unescape input");
+    }
+
+    #[test]
+    fn test_to_posix_record() {
+        let span = Span::test_data();
+        let input = Value::test_record(record! {
+            "FOO" => Value::test_string("bar"),
+            "MESSAGE" => Value::test_string("hello world"),
+            "QUOTE" => Value::test_string("it's"),
+            "PATH" => Value::test_list(vec![Value::test_string("/usr/bin"), Value::test_string("/bin")]),
+        });
+        let statements = exports_from_record(input).unwrap();
+        assert_eq!(
+            statements_to_posix(&statements),
+            "export FOO=bar\nexport MESSAGE='hello world'\nexport QUOTE='it'\\''s'\nexport PATH=/usr/bin:/bin"
+        );
+
+        // the structured table, with the null a bare export leaves
+        let row = |name: &str, value: Value| Value::test_record(record! { "name" => Value::test_string(name), "value" => value });
+        let input = Value::test_list(vec![row("A", Value::test_int(1)), row("EDITOR", Value::nothing(span))]);
+        assert_eq!(statements_to_posix(&exports_from_record(input).unwrap()), "export A=1\nexport EDITOR");
+
+        let input = Value::test_record(record! { "java.home" => Value::test_string("/opt/java") });
+        let error = exports_from_record(input).unwrap_err();
+        assert_eq!(error.msg, "Invalid variable name");
+        assert_eq!(error.labels[0].text, "java.home is not a valid shell variable name");
+    }
+
+    #[test]
+    fn test_typed_structured() {
+        let call = EvaluatedCall::new(Span::test_data()).with_flag(flag("structured")).with_flag(flag("typed"));
+        let mut stderr = Vec::new();
+        let input = "export PORT=8080 DEBUG=true Q='8080' R=0.5".to_string();
+        let output = convert(&call, &[input], None, &mock_env, &mut stderr).unwrap();
+        let values: Vec<Value> = output
+            .into_list()
+            .unwrap()
+            .into_iter()
+            .map(|row| row.into_record().unwrap().get("value").unwrap().clone())
+            .collect();
+        assert_eq!(
+            values,
+            vec![Value::test_int(8080), Value::test_bool(true), Value::test_string("8080"), Value::test_float(0.5)]
+        );
+    }
+}
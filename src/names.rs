@@ -0,0 +1,94 @@
+// turn a variable name into a plain ASCII identifier: accented letters lose
+// their accents, anything else outside `[A-Za-z0-9_]` becomes `_`, and a
+// leading digit gets a `_` in front
+pub fn transliterate(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for ch in name.chars() {
+        match ch {
+            c if c.is_ascii_alphanumeric() || c == '_' => out.push(c),
+            c => match fold_accent(c) {
+                Some(plain) => out.push_str(plain),
+                None => out.push('_'),
+            },
+        }
+    }
+    if out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    out
+}
+
+// a POSIX identifier (`[A-Za-z_][A-Za-z0-9_]*`), with the characters in
+// `extra` also allowed after the first
+pub fn is_valid_name(name: &str, extra: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || extra.contains(c))
+}
+
+// the unaccented spelling of the common Latin letters
+fn fold_accent(c: char) -> Option<&'static str> {
+    let plain = match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'ç' | 'ć' | 'č' => "c",
+        'Ç' | 'Ć' | 'Č' => "C",
+        'ď' | 'đ' => "d",
+        'Ď' | 'Đ' => "D",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ę' | 'ě' => "e",
+        'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ę' | 'Ě' => "E",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' => "i",
+        'Ì' | 'Í' | 'Î' | 'Ï' | 'Ī' => "I",
+        'ł' => "l",
+        'Ł' => "L",
+        'ñ' | 'ń' | 'ň' => "n",
+        'Ñ' | 'Ń' | 'Ň' => "N",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+        'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' | 'Ő' => "O",
+        'ř' => "r",
+        'Ř' => "R",
+        'ś' | 'š' | 'ş' => "s",
+        'Ś' | 'Š' | 'Ş' => "S",
+        'ť' | 'ţ' => "t",
+        'Ť' | 'Ţ' => "T",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => "u",
+        'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ū' | 'Ů' | 'Ű' => "U",
+        'ý' | 'ÿ' => "y",
+        'Ý' | 'Ÿ' => "Y",
+        'ź' | 'ż' | 'ž' => "z",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ß' => "ss",
+        'æ' => "ae",
+        'Æ' => "AE",
+        'œ' => "oe",
+        'Œ' => "OE",
+        _ => return None,
+    };
+    Some(plain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transliterate() {
+        assert_eq!(transliterate("APP-NAME"), "APP_NAME");
+        assert_eq!(transliterate("java.home"), "java_home");
+        assert_eq!(transliterate("CAFÉ_NIÑO"), "CAFE_NINO");
+        assert_eq!(transliterate("straße"), "strasse");
+        assert_eq!(transliterate("9LIVES"), "_9LIVES");
+        assert_eq!(transliterate("日本"), "__");
+        assert_eq!(transliterate("PLAIN_1"), "PLAIN_1");
+    }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("_PATH_2", ""));
+        assert!(!is_valid_name("java.home", ""));
+        assert!(is_valid_name("java.home", "."));
+        assert!(!is_valid_name(".home", "."));
+        assert!(!is_valid_name("2FA", ""));
+        assert!(!is_valid_name("", ""));
+    }
+}
@@ -0,0 +1,595 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{LabeledError, Spanned};
+
+use crate::names::is_valid_name;
+
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    // leading wrapper commands (`sudo export FOO=bar`) dropped before the export check
+    pub strip_prefixes: Vec<String>,
+    // expand `$VAR`, `${VAR}` and `${!VAR}` against earlier exports
+    pub expand: bool,
+    // also accept bare `NAME=value` lines, as found in `.env` files
+    pub dotenv: bool,
+    // drop assignments that weren't `export`ed
+    pub only_exported: bool,
+    // decode `printf %q` output, which also uses bash's `$'...'` quoting
+    pub printf_q: bool,
+    // keep unquoted backslashes literally, for pasted `C:\Users\me` paths
+    pub windows_paths: bool,
+    // env vars Nushell manages itself; assigning them always warns
+    pub reserved_names: Vec<String>,
+    // drop assignments to `reserved_names` instead of just warning
+    pub skip_reserved: bool,
+    // how binary input is decoded
+    pub encoding: Encoding,
+    // also convert `alias name=command` lines
+    pub aliases: bool,
+    // what malformed input does to the conversion
+    pub errors: ErrorMode,
+    // read `source FILE` / `. FILE` relative to this directory and parse the
+    // file in place
+    pub follow_source: Option<PathBuf>,
+    // rewrite names like `APP-NAME` or `CAFÉ` into ASCII identifiers
+    pub transliterate_names: bool,
+    // with `expand`, where references not among the exports are looked up;
+    // the caller's `$env` for --expand. references found in neither expand
+    // to nothing, as in a shell; without it they're kept as written
+    pub ambient_env: Option<HashMap<String, String>>,
+    // decode `%20` style escapes in values
+    pub percent_decode: bool,
+    // keep one assignment per name, the last value in the first place
+    pub dedup: bool,
+    // note which values were quoted, which --typed keeps as strings
+    pub typed: bool,
+    // fold names that differ only by case (`Path`, `PATH`) into one
+    pub case_fold: Option<CaseFold>,
+    // keep the comment ending a line with the exports on it
+    pub keep_comments: bool,
+    // without `expand`, note `$NAME` references so they can be rendered as
+    // Nushell interpolation; implies `subst`
+    pub interpolate: bool,
+    // note `$(command)` and `` `command` `` substitutions so they can be
+    // rendered as Nushell subexpressions, instead of commenting them out
+    pub subst: bool,
+    // refuse names that aren't POSIX identifiers, give or take `allowed_name_chars`
+    pub strict_names: bool,
+    // characters names may also contain, like the `.` in `java.home`, with
+    // or without `strict_names`
+    pub allowed_name_chars: String,
+    // also keep the exports in function bodies, which only run when the
+    // function is called
+    pub function_exports: bool,
+    // skip the shell's prompt settings, `PROMPT_NAMES`
+    pub strip_prompts: bool,
+}
+
+pub const DEFAULT_STRIP_PREFIXES: &[&str] = &["sudo", "time", "command", "exec", "env"];
+
+pub const DEFAULT_RESERVED_NAMES: &[&str] = &["PWD", "FILE_PWD", "CURRENT_FILE"];
+
+// prompt settings a bashrc makes that Nushell doesn't use; it has its own
+// PROMPT_COMMAND, which takes a closure rather than a command line
+pub const PROMPT_NAMES: &[&str] = &["PS1", "PS2", "PROMPT_COMMAND"];
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            strip_prefixes: DEFAULT_STRIP_PREFIXES.iter().map(|p| p.to_string()).collect(),
+            expand: false,
+            dotenv: false,
+            only_exported: false,
+            printf_q: false,
+            windows_paths: false,
+            reserved_names: DEFAULT_RESERVED_NAMES.iter().map(|n| n.to_string()).collect(),
+            skip_reserved: false,
+            encoding: Encoding::default(),
+            aliases: false,
+            errors: ErrorMode::default(),
+            follow_source: None,
+            transliterate_names: false,
+            ambient_env: None,
+            percent_decode: false,
+            dedup: false,
+            typed: false,
+            case_fold: None,
+            keep_comments: false,
+            interpolate: false,
+            subst: false,
+            strict_names: false,
+            allowed_name_chars: String::new(),
+            function_exports: false,
+            strip_prompts: false,
+        }
+    }
+}
+
+// a bundle of flags for a common kind of input; flags given explicitly
+// (`--keep-comments=false`) win over the profile's
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    // --dotenv --trailing-newline
+    Dotenv,
+    // --keep-comments --strip-prompts, following `source` from the home
+    // directory, where a bashrc's neighbours are
+    Bashrc,
+    // --strict --validate-only
+    StrictLint,
+}
+
+impl std::str::FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dotenv" => Ok(Profile::Dotenv),
+            "bashrc" => Ok(Profile::Bashrc),
+            "strict-lint" => Ok(Profile::StrictLint),
+            other => Err(format!("unknown profile '{}', expected dotenv, bashrc or strict-lint", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CaseFold {
+    // the spelling the name first appeared with
+    #[default]
+    First,
+    Upper,
+}
+
+impl std::str::FromStr for CaseFold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(CaseFold::First),
+            "upper" => Ok(CaseFold::Upper),
+            other => Err(format!("unknown casing '{}', expected first or upper", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ErrorMode {
+    // skip it quietly
+    #[default]
+    Lenient,
+    // convert the rest and list what broke alongside the output
+    Recover,
+    // fail the whole conversion
+    Strict,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Encoding {
+    // UTF-16 when the input starts with its byte order mark, else UTF-8
+    #[default]
+    Auto,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Encoding::Auto),
+            "utf8" | "utf-8" => Ok(Encoding::Utf8),
+            "utf16le" | "utf-16le" => Ok(Encoding::Utf16Le),
+            "utf16be" | "utf-16be" => Ok(Encoding::Utf16Be),
+            other => Err(format!("unknown encoding '{}', expected auto, utf8, utf16le or utf16be", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum QuoteStyle {
+    // bare when plain, double-quoted otherwise
+    #[default]
+    Auto,
+    // like auto, but values with backslashes become raw strings (r#'...'#)
+    Raw,
+}
+
+impl std::str::FromStr for QuoteStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(QuoteStyle::Auto),
+            "raw" => Ok(QuoteStyle::Raw),
+            other => Err(format!("unknown quote style '{}', expected auto or raw", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CommentStyle {
+    // `# note`, as Nushell reads it
+    #[default]
+    Hash,
+    // `// note`, for pasting the output elsewhere
+    Slash,
+}
+
+impl CommentStyle {
+    pub fn marker(self) -> &'static str {
+        match self {
+            CommentStyle::Hash => "#",
+            CommentStyle::Slash => "//",
+        }
+    }
+}
+
+impl std::str::FromStr for CommentStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hash" => Ok(CommentStyle::Hash),
+            "slash" => Ok(CommentStyle::Slash),
+            other => Err(format!("unknown comment style '{}', expected hash or slash", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub format: Format,
+    pub quote_style: QuoteStyle,
+    // how the comments we write (notes, errors) are marked
+    pub comment_style: CommentStyle,
+    // append a `# source: N` comment to exports that know their input index
+    pub source_labels: bool,
+    // escape the whole output for use inside a Nushell `$"..."` string
+    pub escape_for_interpolation: bool,
+    // render the `path_vars` as Nushell lists split on `:`
+    pub split_paths: bool,
+    pub path_vars: Vec<String>,
+    // drop repeated segments from split paths
+    pub deduplicate_paths: bool,
+    // render the `split_vars` as Nushell lists split on `split_on`
+    pub split_on: char,
+    pub split_vars: Vec<String>,
+    // render empty and `your-key-here` style values as `null`
+    pub placeholders_as_null: bool,
+    // render empty values as an `input` prompt; wins over `placeholders_as_null`
+    pub prompt_empty: bool,
+    // start from the current `$env` and overlay the parsed exports
+    pub merge: Option<MergeMode>,
+    // render `LS_COLORS` as a record of code to style
+    pub parse_ls_colors: bool,
+    // append a `# int` / `# path` / ... comment with the type of each value
+    pub annotate_types: bool,
+    // emit exports, warnings, skipped input and counts as one JSON object
+    pub report_json: bool,
+    // emit one `load-env { ... }` record instead of an assignment per line
+    pub load_env: bool,
+    // end the output with a newline, as a file would
+    pub trailing_newline: bool,
+    // check the input and report what's wrong with it, with no output
+    pub validate_only: bool,
+    // write unquoted numbers and `true`/`false` as Nushell ints, floats and
+    // bools, and anything else Nushell would read that way as a string
+    pub typed: bool,
+    // put in front of every name written, so `APP_` turns `PORT` into `APP_PORT`
+    pub prefix: String,
+}
+
+pub const DEFAULT_PATH_VARS: &[&str] = &["PATH", "MANPATH", "LD_LIBRARY_PATH"];
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            format: Format::default(),
+            quote_style: QuoteStyle::default(),
+            comment_style: CommentStyle::default(),
+            source_labels: false,
+            escape_for_interpolation: false,
+            split_paths: false,
+            path_vars: DEFAULT_PATH_VARS.iter().map(|v| v.to_string()).collect(),
+            deduplicate_paths: false,
+            split_on: ',',
+            split_vars: Vec::new(),
+            placeholders_as_null: false,
+            prompt_empty: false,
+            merge: None,
+            parse_ls_colors: false,
+            annotate_types: false,
+            report_json: false,
+            load_env: false,
+            trailing_newline: false,
+            validate_only: false,
+            typed: false,
+            prefix: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MergeMode {
+    // the whole current environment with the parsed exports laid over it
+    #[default]
+    Merged,
+    // only the parsed exports that change the current environment
+    Overlay,
+}
+
+impl std::str::FromStr for MergeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merged" => Ok(MergeMode::Merged),
+            "overlay" => Ok(MergeMode::Overlay),
+            other => Err(format!("unknown merge output '{}', expected merged or overlay", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Format {
+    // `$env.NAME = value` assignments
+    #[default]
+    Nushell,
+    // `name<TAB>value` rows for tools
+    Tsv,
+    // one flat `{"NAME": "value"}` JSON object
+    JsonEnv,
+    // `NAME := value` Makefile assignments
+    Make,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nushell" => Ok(Format::Nushell),
+            "tsv" => Ok(Format::Tsv),
+            "json-env" => Ok(Format::JsonEnv),
+            "make" => Ok(Format::Make),
+            other => Err(format!("unknown format '{}', expected nushell, tsv, json-env or make", other)),
+        }
+    }
+}
+
+impl Profile {
+    // the --profile of a call, if it has one
+    pub fn from_call(call: &EvaluatedCall) -> Result<Option<Self>, LabeledError> {
+        match call.get_flag::<Spanned<String>>("profile")? {
+            Some(profile) => Ok(Some(parse_flag(profile, "Invalid profile")?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl ParseOptions {
+    // read the parsing flags of a `from posix` call
+    pub fn from_call(call: &EvaluatedCall) -> Result<Self, LabeledError> {
+        let mut options = ParseOptions::default();
+        let profile = Profile::from_call(call)?;
+        let bashrc = profile == Some(Profile::Bashrc);
+        if let Some(prefixes) = call.get_flag::<Vec<String>>("strip-prefixes")? {
+            options.strip_prefixes = prefixes;
+        }
+        // --expand-env needs the engine, so the caller fills in `ambient_env`
+        options.expand = call.has_flag("expand")? || call.has_flag("expand-env")?;
+        options.dotenv = switch(call, "dotenv", profile == Some(Profile::Dotenv))? || call.has_flag("no-export-keyword")?;
+        options.only_exported = call.has_flag("only-exported")?;
+        options.printf_q = call.has_flag("printf-q")?;
+        options.windows_paths = call.has_flag("windows-paths")?;
+        if let Some(names) = call.get_flag::<Vec<String>>("reserved-names")? {
+            options.reserved_names = names;
+        }
+        options.skip_reserved = call.has_flag("skip-reserved")?;
+        options.strip_prompts = switch(call, "strip-prompts", bashrc)?;
+        options.aliases = call.has_flag("aliases")?;
+        options.transliterate_names = call.has_flag("transliterate-names")?;
+        options.percent_decode = call.has_flag("percent-decode")?;
+        options.keep_comments = switch(call, "keep-comments", bashrc)?;
+        options.interpolate = call.has_flag("interpolate")?;
+        options.subst = call.has_flag("subst")?;
+        options.function_exports = call.has_flag("include-function-exports")?;
+        options.strict_names = call.has_flag("strict-names")?;
+        if let Some(chars) = call.get_flag::<String>("allowed-name-chars")? {
+            options.allowed_name_chars = chars;
+        }
+        options.dedup = call.has_flag("dedup")?;
+        options.typed = call.has_flag("typed")?;
+        if call.has_flag("case-insensitive-dedup")? {
+            options.case_fold = Some(match call.get_flag::<Spanned<String>>("dedup-case")? {
+                Some(casing) => parse_flag(casing, "Invalid casing")?,
+                None => CaseFold::default(),
+            });
+        }
+        // a bashrc sources its neighbours in the home directory; that's the
+        // user's `$env.HOME`, so the caller fills it in like `ambient_env`
+        options.follow_source = call.get_flag::<String>("follow-source")?.map(PathBuf::from);
+        // an explicit --recover wins over the strict-lint profile, an
+        // explicit --strict still conflicts with it
+        let recover = call.has_flag("recover")?;
+        let strict = switch(call, "strict", profile == Some(Profile::StrictLint) && !recover)?;
+        options.errors = match (strict, recover) {
+            (true, true) => {
+                let span = call.get_flag_span("recover").unwrap_or(call.head);
+                return Err(LabeledError::new("Conflicting flags")
+                    .with_label("--recover can't be combined with --strict", span));
+            }
+            (true, false) => ErrorMode::Strict,
+            (false, true) => ErrorMode::Recover,
+            (false, false) => ErrorMode::Lenient,
+        };
+        if let Some(encoding) = call.get_flag::<Spanned<String>>("encoding")? {
+            options.encoding = parse_flag(encoding, "Invalid encoding")?;
+        }
+
+        Ok(options)
+    }
+}
+
+impl RenderOptions {
+    // read the output flags of a `from posix` call
+    pub fn from_call(call: &EvaluatedCall) -> Result<Self, LabeledError> {
+        let mut options = RenderOptions::default();
+        let profile = Profile::from_call(call)?;
+        options.trailing_newline = switch(call, "trailing-newline", profile == Some(Profile::Dotenv))?;
+        options.validate_only = switch(call, "validate-only", profile == Some(Profile::StrictLint))?;
+        if let Some(style) = call.get_flag::<Spanned<String>>("quote-style")? {
+            options.quote_style = parse_flag(style, "Invalid quote style")?;
+        }
+        if let Some(style) = call.get_flag::<Spanned<String>>("comment-style")? {
+            options.comment_style = parse_flag(style, "Invalid comment style")?;
+        }
+        // `--target` is another name for `--format`
+        let format = call.get_flag::<Spanned<String>>("format")?;
+        let target = call.get_flag::<Spanned<String>>("target")?;
+        if let (Some(_), Some(target)) = (&format, &target) {
+            return Err(LabeledError::new("Conflicting flags")
+                .with_label("--target is the same as --format, give only one", target.span));
+        }
+        if let Some(format) = format.or(target) {
+            options.format = parse_flag(format, "Invalid format")?;
+        }
+        options.source_labels = call.has_flag("source-labels")?;
+        options.escape_for_interpolation = call.has_flag("escape-for-interpolation")?;
+        options.split_paths = call.has_flag("split-paths")?;
+        if let Some(vars) = call.get_flag::<Vec<String>>("path-vars")? {
+            options.path_vars = vars;
+        }
+        options.deduplicate_paths = call.has_flag("deduplicate-paths")?;
+        if let Some(separator) = call.get_flag::<Spanned<String>>("split-on")? {
+            let mut chars = separator.item.chars();
+            options.split_on = match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => {
+                    return Err(LabeledError::new("Invalid separator")
+                        .with_label("expected a single character", separator.span));
+                }
+            };
+        }
+        if let Some(vars) = call.get_flag::<Vec<String>>("split-vars")? {
+            options.split_vars = vars;
+        }
+        options.placeholders_as_null = call.has_flag("placeholders-as-null")?;
+        options.prompt_empty = call.has_flag("prompt-empty")?;
+        options.report_json = call.has_flag("report-json")?;
+        options.parse_ls_colors = call.has_flag("parse-ls-colors")?;
+        options.annotate_types = call.has_flag("annotate-types")?;
+        options.load_env = call.has_flag("load-env")?;
+        options.typed = call.has_flag("typed")?;
+        // a prefix that's an identifier keeps every name one
+        if let Some(prefix) = call.get_flag::<Spanned<String>>("prefix")? {
+            if !prefix.item.is_empty() && !is_valid_name(&prefix.item, "") {
+                return Err(LabeledError::new("Invalid prefix")
+                    .with_label("expected letters, digits and _, not starting with a digit", prefix.span));
+            }
+            options.prefix = prefix.item;
+        }
+        if call.has_flag("merge-with-current-env")? {
+            options.merge = Some(match call.get_flag::<Spanned<String>>("merge-output")? {
+                Some(mode) => parse_flag(mode, "Invalid merge output")?,
+                None => MergeMode::default(),
+            });
+        }
+
+        Ok(options)
+    }
+}
+
+// a switch that defaults to `preset` unless it was given, as `--name` or
+// `--name=false`
+fn switch(call: &EvaluatedCall, name: &str, preset: bool) -> Result<bool, LabeledError> {
+    match call.get_flag_span(name) {
+        Some(_) => Ok(call.has_flag(name)?),
+        None => Ok(preset),
+    }
+}
+
+fn parse_flag<T>(flag: Spanned<String>, error: &str) -> Result<T, LabeledError>
+where
+    T: std::str::FromStr<Err = String>,
+{
+    flag.item.parse().map_err(|msg: String| LabeledError::new(error).with_label(msg, flag.span))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_posix_exports_with, Export};
+    use crate::render::render;
+    use nu_protocol::{Span, Value};
+
+    fn flag(name: &str) -> Spanned<String> {
+        Spanned { item: name.to_string(), span: Span::test_data() }
+    }
+
+    #[test]
+    fn test_options_from_call() {
+        let call = EvaluatedCall::new(Span::test_data())
+            .with_flag(flag("expand"))
+            .with_named(flag("format"), Value::test_string("tsv"));
+
+        let parse = ParseOptions::from_call(&call).unwrap();
+        let render_options = RenderOptions::from_call(&call).unwrap();
+        assert!(parse.expand);
+        assert_eq!(parse.strip_prefixes.len(), DEFAULT_STRIP_PREFIXES.len());
+        assert_eq!(render_options.format, Format::Tsv);
+        assert_eq!(render_options.quote_style, QuoteStyle::Auto);
+    }
+
+    #[test]
+    fn test_options_from_call_rejects_unknown_format() {
+        let call = EvaluatedCall::new(Span::test_data())
+            .with_named(flag("format"), Value::test_string("xml"));
+        assert!(RenderOptions::from_call(&call).is_err());
+    }
+
+    #[test]
+    fn test_target_is_format() {
+        let call = EvaluatedCall::new(Span::test_data())
+            .with_named(flag("target"), Value::test_string("json-env"));
+        assert_eq!(RenderOptions::from_call(&call).unwrap().format, Format::JsonEnv);
+
+        let call = call.with_named(flag("format"), Value::test_string("tsv"));
+        assert!(RenderOptions::from_call(&call).is_err());
+    }
+
+    #[test]
+    fn test_split_on_takes_one_character() {
+        let call = EvaluatedCall::new(Span::test_data())
+            .with_named(flag("split-on"), Value::test_string(";"));
+        assert_eq!(RenderOptions::from_call(&call).unwrap().split_on, ';');
+
+        let call = EvaluatedCall::new(Span::test_data())
+            .with_named(flag("split-on"), Value::test_string(", "));
+        assert!(RenderOptions::from_call(&call).is_err());
+    }
+
+    #[test]
+    fn test_prefix_must_be_an_identifier() {
+        let call = EvaluatedCall::new(Span::test_data())
+            .with_named(flag("prefix"), Value::test_string("APP_"));
+        assert_eq!(RenderOptions::from_call(&call).unwrap().prefix, "APP_");
+
+        let call = EvaluatedCall::new(Span::test_data())
+            .with_named(flag("prefix"), Value::test_string("1-"));
+        assert_eq!(RenderOptions::from_call(&call).unwrap_err().msg, "Invalid prefix");
+    }
+
+    #[test]
+    fn test_options_constructed_directly() {
+        let parse = ParseOptions { strip_prefixes: vec![], expand: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export A=1 B=$A\nsudo export C=3", &parse);
+        assert_eq!(exports, vec![Export::new("A", "1"), Export::new("B", "1")]);
+
+        let render_options = RenderOptions { format: Format::Tsv, ..Default::default() };
+        assert_eq!(render(exports, &render_options), "A\t1\nB\t1");
+    }
+}
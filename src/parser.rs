@@ -1,206 +1,2536 @@
+use std::borrow::Cow;
+use std::ops::Range;
+use std::path::PathBuf;
 
-#[derive(Debug, PartialEq)]
+use crate::expand::{expand_value_tracked, is_name, matching_brace, matching_paren, name_prefix_len, parse_operator};
+use crate::names::{is_valid_name, transliterate};
+use crate::options::{CaseFold, ErrorMode, MergeMode, ParseOptions, PROMPT_NAMES};
+use crate::word::{percent_decode, split_word, Piece};
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Export {
     pub name: String,
     pub value: String,
+    // index of the input item this came from, when parsing several sources
+    pub source: Option<usize>,
+    // false for plain shell assignments (`FOO=1` in dotenv mode, `: ${FOO:=1}`)
+    pub exported: bool,
+    // the `# note` that ended its line, kept with --keep-comments
+    pub comment: Option<String>,
+    // with --interpolate or --subst, the value as text and the references
+    // in it, when it has any; `value` still holds them as written
+    pub parts: Option<Vec<Part>>,
+    // runs a command substitution that wasn't converted, so it's written
+    // out commented for doing by hand
+    pub unconverted: bool,
+    // with --typed, some of the value was quoted or escaped, so it stays a
+    // string even if it reads as a number
+    pub quoted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Part {
+    Text(String),
+    // a `$NAME` or `${NAME}` left for Nushell to look up
+    Env(String),
+    // a `${NAME:-word}` or `${NAME-word}` (`=` for `-` alike): NAME, or
+    // `word` when it's unset, or also when it's empty with the colon
+    Default { name: String, word: Vec<Part>, empty: bool },
+    // a `$(command)` or `` `command` ``, run by Nushell when the output is
+    // sourced; substitutions nested in it are already subexpressions
+    Command(String),
+}
+
+impl Export {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Export {
+            name: name.into(),
+            value: value.into(),
+            source: None,
+            exported: true,
+            comment: None,
+            parts: None,
+            unconverted: false,
+            quoted: false,
+        }
+    }
 }
 
 pub fn parse_posix_exports(input: &str) -> Vec<Export> {
-    let mut exports = Vec::new();
+    parse_posix_exports_with(input, &ParseOptions::default())
+}
 
-    // handle multiline input
-    for line in input.lines() {
-        // split by && to handle multiple commands on same line
-        for segment in line.split("&&") {
-            let trimmed = segment.trim();
+pub fn parse_posix_exports_with(input: &str, options: &ParseOptions) -> Vec<Export> {
+    parse_posix_report(input, options).into_exports()
+}
+
+// like `parse_posix_exports`, for input read a line at a time
+pub fn parse_posix_exports_lines<I: Iterator<Item = String>>(lines: I) -> Vec<Export> {
+    parse_posix_report_lines(lines, &ParseOptions::default()).into_exports()
+}
+
+// like `parse_posix_exports_with`, but input that can't have meant what it
+// says, like a quote that never closes, is an error rather than skipped
+pub fn try_parse_posix_exports(input: &str, options: &ParseOptions) -> Result<Vec<Export>, ParseError> {
+    let report = parse_posix_report(input, options);
+    if let Some(error) = report.errors().find(|error| error.reason == UNTERMINATED_QUOTE) {
+        return Err(ParseError::from(error));
+    }
+    Ok(report.into_exports())
+}
 
-            // check if this is an export command
-            if trimmed.starts_with("export ") {
-                let export_content = &trimmed[7..].trim(); // remove "export "
-                parse_export_content(export_content, &mut exports);
-            } else if trimmed.starts_with("export") && trimmed.len() > 6 {
-                // handle cases like "export VAR=value" without space
-                let export_content = &trimmed[6..].trim();
-                parse_export_content(export_content, &mut exports);
+// lay the parsed statements over the `current` environment; a parsed export
+// replaces the current variable in place, new ones go at the end, and an
+// `unset` takes the variable out. An overlay only has the exports that
+// change the environment, so an `unset` there only drops the parsed
+// exports before it
+pub fn merge_with_env(current: Vec<Export>, statements: Vec<Statement>, mode: MergeMode) -> Vec<Export> {
+    let mut merged = match mode {
+        MergeMode::Merged => current.clone(),
+        MergeMode::Overlay => Vec::new(),
+    };
+    for statement in statements {
+        match statement {
+            Statement::Set(export) if mode == MergeMode::Overlay => {
+                if !current.iter().any(|c| c.name == export.name && c.value == export.value) {
+                    merged.push(export);
+                }
             }
+            Statement::Set(export) => match merged.iter_mut().find(|existing| existing.name == export.name) {
+                Some(existing) => *existing = export,
+                None => merged.push(export),
+            },
+            Statement::Unset(name) => merged.retain(|export| export.name != name),
+            Statement::Alias { .. } | Statement::Reexport { .. } => {}
+        }
+    }
+    merged
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    // 1-based line of the input the warning is about
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// input that looked like it meant something but couldn't be used
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skipped {
+    pub line: usize,
+    pub text: String,
+    pub reason: String,
+    // broken input, as opposed to a command that just isn't an export;
+    // these are what `--strict` fails on
+    pub malformed: bool,
+    // where `text` sits in the input, in bytes, when that's known
+    pub span: Option<Range<usize>>,
+}
+
+impl std::fmt::Display for Skipped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.text, self.reason)
+    }
+}
+
+// why `try_parse_posix_exports` gave up
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub text: String,
+    pub reason: String,
+    // where `text` sits in the input, in bytes
+    pub span: Option<Range<usize>>,
+}
+
+impl From<&Skipped> for ParseError {
+    fn from(skipped: &Skipped) -> Self {
+        ParseError {
+            line: skipped.line,
+            text: skipped.text.clone(),
+            reason: skipped.reason.clone(),
+            span: skipped.span.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.text, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// one thing the input does, in the order it does it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Set(Export),
+    // `alias name=command`, with the command unquoted
+    Alias { name: String, command: String },
+    // `export NAME` on its own, exporting whatever NAME already holds
+    Reexport { name: String },
+    // `unset NAME`
+    Unset(String),
+}
+
+impl Statement {
+    pub fn as_export(&self) -> Option<&Export> {
+        match self {
+            Statement::Set(export) => Some(export),
+            _ => None,
         }
     }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseReport {
+    pub statements: Vec<Statement>,
+    // non-fatal problems, like references `--expand` couldn't resolve
+    pub warnings: Vec<Warning>,
+    pub skipped: Vec<Skipped>,
+    // number of input lines read
+    pub lines: usize,
+}
+
+impl ParseReport {
+    // the skipped input that was actually broken
+    pub fn errors(&self) -> impl Iterator<Item = &Skipped> {
+        self.skipped.iter().filter(|skipped| skipped.malformed)
+    }
+
+    // just the assignments, in order
+    pub fn exports(&self) -> Vec<Export> {
+        self.statements.iter().filter_map(Statement::as_export).cloned().collect()
+    }
 
-    exports
+    pub fn into_exports(self) -> Vec<Export> {
+        self.statements
+            .into_iter()
+            .filter_map(|statement| match statement {
+                Statement::Set(export) => Some(export),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// parse exports and collect warnings along the way
+pub fn parse_posix_report(input: &str, options: &ParseOptions) -> ParseReport {
+    let mut parser = Parser::new(options);
+    parser.report.lines = parser.parse_input(input);
+
+    parser.finish()
+}
+
+// like `parse_posix_report`, for input read a line at a time; the lines may
+// keep their `\n` or not, and only the lines a logical line may still go
+// on into are held at once, no more than `MAX_CONTINUED_LINES`
+pub fn parse_posix_report_lines(lines: impl Iterator<Item = String>, options: &ParseOptions) -> ParseReport {
+    let mut parser = Parser::new(options);
+    parser.report.lines = parser.parse_lines(lines);
+
+    parser.finish()
+}
+
+// parse `line` as one logical line, as is: nothing is joined onto it, so a
+// quote left open just runs to its end
+pub fn parse_posix_line(line: &str, options: &ParseOptions) -> Vec<Export> {
+    let mut parser = Parser::new(options);
+    parser.line = 1;
+    parser.parse_line(line);
+    parser.report.lines = 1;
+
+    parser.finish().into_exports()
 }
 
-fn parse_export_content(content: &str, exports: &mut Vec<Export>) {
-    let mut chars = content.chars().peekable();
-    let mut current_var = String::new();
-    let mut in_quotes = false;
-    let mut quote_char = ' ';
+// the first line, joined with the lines after it that continue it, and how
+// many lines that used. a quote left open runs on into the next line, as
+// does a command ending in `&&`, `||` or `|`, and an unquoted `\` at the end
+// joins the next line on directly; a quote that never closes is left to the
+// lines before it. `None` when `more` lines may follow and it might go on
+// into them
+fn logical_line<'a>(lines: &[&'a str], more: bool) -> Option<(Cow<'a, str>, usize)> {
+    let mut joined = Cow::Borrowed(lines[0]);
+    let mut used = 1;
+    let mut quote = open_quote(lines[0], None);
+    let mut settled = None;
 
-    while let Some(ch) = chars.next() {
-        match ch {
-            '"' | '\'' if !in_quotes => {
-                in_quotes = true;
-                quote_char = ch;
-                current_var.push(ch);
+    loop {
+        let continued = quote.is_some() || ends_with_escape(&joined) || ends_with_operator(&joined);
+        if more && used == lines.len() && continued {
+            return None;
+        }
+        if quote.is_none() && used < lines.len() && ends_with_escape(&joined) {
+            joined.to_mut().pop();
+        } else if quote.is_none() {
+            if used == lines.len() || !ends_with_operator(&joined) {
+                return Some((joined, used));
             }
-            '"' | '\'' if in_quotes && ch == quote_char => {
-                // check if escaped
-                if current_var.ends_with('\\') {
-                    current_var.push(ch);
-                } else {
-                    in_quotes = false;
-                    current_var.push(ch);
+            settled = Some((joined.clone(), used));
+            joined.to_mut().push(' ');
+        } else if used == lines.len() {
+            return Some(settled.unwrap_or((Cow::Borrowed(lines[0]), 1)));
+        } else {
+            joined.to_mut().push('\n');
+        }
+        joined.to_mut().push_str(lines[used]);
+        quote = open_quote(lines[used], quote);
+        used += 1;
+    }
+}
+
+// a line without the `\n` or `\r\n` ending it
+fn line_text(line: &str) -> &str {
+    match line.strip_suffix('\n') {
+        Some(text) => text.strip_suffix('\r').unwrap_or(text),
+        None => line,
+    }
+}
+
+// whether a line ends in `&&`, `||` or a pipe, and so goes on to the next
+fn ends_with_operator(line: &str) -> bool {
+    let code = strip_comment(line).trim_end();
+    code.ends_with("&&") || code.ends_with('|')
+}
+
+// whether the line ends in a backslash escaping the newline after it
+fn ends_with_escape(line: &str) -> bool {
+    let code = strip_comment(line);
+    let backslashes = code.len() - code.trim_end_matches('\\').len();
+    code.len() == line.len() && !backslashes.is_multiple_of(2)
+}
+
+// the quote still open at the end of `line`, when it started inside `quote`;
+// an unquoted `#` starting a word comments out the rest
+fn open_quote(line: &str, mut quote: Option<char>) -> Option<char> {
+    let mut escaped = false;
+    let mut word_start = true;
+    for ch in line.chars() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, ch) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (Some('"'), '"') => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, '#') if word_start => return None,
+            _ => {}
+        }
+        word_start = quote.is_none() && (ch.is_whitespace() || matches!(ch, ';' | '&' | '|'));
+    }
+    quote
+}
+
+// drop a comment from the line; it starts at an unquoted `#` beginning a
+// word, so `a#b` and quoted `#`s stay, and goes before the line is split so
+// separators in it don't count
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut word_start = true;
+    for (i, ch) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            word_start = false;
+            continue;
+        }
+        match (quote, ch) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (Some('"'), '"') => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, '#') if word_start => return &line[..i],
+            _ => {}
+        }
+        word_start = quote.is_none() && (ch.is_whitespace() || matches!(ch, ';' | '&' | '|'));
+    }
+    line
+}
+
+// `NAME=value` with nothing in front of it
+fn is_bare_assignment(segment: &str) -> bool {
+    segment.split_once('=').is_some_and(|(name, _)| is_name(name))
+}
+
+// parse several inputs (e.g. one `.env` file per list item) in order,
+// tagging every export with the index of the input it came from
+pub fn parse_posix_sources<S: AsRef<str>>(sources: impl IntoIterator<Item = S>, options: &ParseOptions) -> ParseReport {
+    let mut report = ParseReport::default();
+    for (index, source) in sources.into_iter().enumerate() {
+        let parsed = parse_posix_report(source.as_ref(), options);
+        report.statements.extend(parsed.statements.into_iter().map(|statement| match statement {
+            Statement::Set(export) => Statement::Set(Export { source: Some(index), ..export }),
+            other => other,
+        }));
+        report.warnings.extend(parsed.warnings);
+        report.skipped.extend(parsed.skipped);
+        report.lines += parsed.lines;
+    }
+    report
+}
+
+// how a command is joined to the one before it
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Link {
+    // `;`, `&&`, `||`, `&` or the start of the line
+    Sequence,
+    // `|` or `|&`, which feeds it the output of the command before
+    Pipe,
+    // `;;`, `;&` or `;;&`, which end an arm of a `case`
+    CaseArm,
+}
+
+// split a line into commands on `;`, `&&`, `||`, `&` and `|`, ignoring any
+// separators that appear inside quotes or after a backslash; each command
+// comes with where it starts in the line and how it's joined to the one
+// before it
+fn split_commands(line: &str) -> Vec<(usize, &str, Link)> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut link = Link::Sequence;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((i, ch)) = chars.next() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, ch) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (Some('"'), '"') => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, ';') => {
+                segments.push((start, &line[start..i], link));
+                link = Link::Sequence;
+                start = i + 1;
+                let next = chars.peek().map(|&(_, next)| next);
+                if matches!(next, Some(';' | '&')) {
+                    chars.next();
+                    link = Link::CaseArm;
+                    start += 1;
+                    if next == Some(';') && chars.next_if(|&(_, next)| next == '&').is_some() {
+                        start += 1;
+                    }
                 }
             }
-            ' ' | '\t' if !in_quotes => {
-                // end of current variable
-                if !current_var.is_empty() {
-                    if let Some(eq_pos) = current_var.find('=') {
-                        let name = current_var[..eq_pos].to_string();
-                        let value = parse_value(&current_var[eq_pos + 1..]);
-                        exports.push(Export { name, value });
+            (None, '&' | '|') => {
+                // `&&`, `||` and a lone `&` all end a command, and a lone `|`
+                // (or `|&`) starts the next command of a pipe; a lone `&` with
+                // no space after it (`?a=1&b=2`) is taken as part of the
+                // word, where a shell would split
+                let next = chars.peek().map(|&(_, next)| next);
+                if next == Some(ch) {
+                    chars.next();
+                    segments.push((start, &line[start..i], link));
+                    link = Link::Sequence;
+                    start = i + 2;
+                } else if ch == '|' {
+                    segments.push((start, &line[start..i], link));
+                    link = Link::Pipe;
+                    start = i + 1;
+                    if next == Some('&') {
+                        chars.next();
+                        start += 1;
                     }
-                    current_var.clear();
+                } else if next.is_none_or(char::is_whitespace) {
+                    segments.push((start, &line[start..i], link));
+                    link = Link::Sequence;
+                    start = i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    segments.push((start, &line[start..], link));
+
+    segments
+}
+
+// words that open the body of a loop, conditional or group, leaving a
+// command behind them (`for x in a b; do export FOO=$x; done`)
+const LEADING_KEYWORDS: &[&str] = &["do", "then", "else", "{"];
+
+// words starting a segment that's only control flow: the condition of an
+// `if` or loop, or what closes a block. The commands inside the block are
+// their own segments, after `then` or `do`
+const CONTROL_KEYWORDS: &[&str] = &["if", "elif", "fi", "for", "select", "while", "until", "done", "case", "esac", "}"];
+
+// what follows the `in` of a `case WORD in` header: the first arm, if
+// it's on the same line
+fn case_header(segment: &str) -> Option<&str> {
+    let rest = after_word(segment, "case")?.trim_start();
+    let rest = rest[rest.find(char::is_whitespace)?..].trim_start();
+    if rest == "in" {
+        return Some("");
+    }
+    after_word(rest, "in").map(str::trim_start)
+}
+
+fn is_control_flow(segment: &str) -> bool {
+    segment.split_whitespace().next().is_some_and(|word| CONTROL_KEYWORDS.contains(&word))
+}
+
+// drop leading keywords; only whole words at the start of the statement go,
+// so names like `do` after `export` are untouched
+fn strip_keywords(segment: &str) -> &str {
+    let mut rest = segment;
+    loop {
+        let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if !LEADING_KEYWORDS.contains(&&rest[..word_end]) {
+            return rest;
+        }
+        rest = rest[word_end..].trim_start();
+    }
+}
+
+// flags of the wrapper commands that take a value, which is the next word
+// unless it's joined on (`-uroot`, `--user=root`)
+const WRAPPER_VALUE_FLAGS: &[(&str, &[&str])] = &[
+    ("sudo", &["-u", "--user", "-g", "--group", "-C", "--close-from", "-D", "--chdir", "-p", "--prompt", "-R", "--chroot", "-r", "--role", "-t", "--type", "-T", "--command-timeout", "-U", "--other-user"]),
+    ("env", &["-u", "--unset", "-C", "--chdir", "-S", "--split-string"]),
+    ("time", &["-f", "--format", "-o", "--output"]),
+    ("exec", &["-a"]),
+];
+
+// whether the next word is the value of a flag; in a run of short flags
+// (`-Eu`) the first one taking a value takes the rest of the run, or the next
+// word if it's last
+fn flag_takes_value(flag: &str, value_flags: &[&str]) -> bool {
+    if flag.starts_with("--") {
+        return value_flags.contains(&flag);
+    }
+    let letters = &flag[1..];
+    letters
+        .char_indices()
+        .find(|&(_, letter)| value_flags.contains(&format!("-{}", letter).as_str()))
+        .is_some_and(|(i, letter)| i + letter.len_utf8() == letters.len())
+}
+
+// where the first word of a segment ends, keeping quoted blanks in it
+fn first_word_end(segment: &str) -> usize {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, ch) in segment.char_indices() {
+        match (escaped, quote, ch) {
+            (true, ..) => escaped = false,
+            (_, Some(q), _) if ch == q => quote = None,
+            (_, Some('\''), _) => {}
+            (_, _, '\\') => escaped = true,
+            (_, None, '"' | '\'') => quote = Some(ch),
+            (_, None, _) if ch.is_whitespace() => return i,
+            _ => {}
+        }
+    }
+    segment.len()
+}
+
+// drop wrapper commands like `sudo` or `time` from the start of a segment so
+// the export behind them is still found, along with their `-flags`, the
+// values of those flags and the `NAME=value`s they set (`env FOO=1`)
+fn strip_wrapper_prefixes<'a>(segment: &'a str, prefixes: &[String]) -> &'a str {
+    let mut rest = segment;
+    loop {
+        let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let word = &rest[..word_end];
+        if !prefixes.iter().any(|p| p == word) {
+            return rest;
+        }
+        let value_flags = WRAPPER_VALUE_FLAGS.iter().find(|(wrapper, _)| *wrapper == word).map_or(&[][..], |(_, flags)| flags);
+        rest = rest[word_end..].trim_start();
+        loop {
+            let end = first_word_end(rest);
+            let next = &rest[..end];
+            if next == "--" {
+                rest = rest[end..].trim_start();
+                break;
+            } else if next.starts_with('-') {
+                rest = rest[end..].trim_start();
+                if flag_takes_value(next, value_flags) {
+                    rest = rest[first_word_end(rest)..].trim_start();
                 }
+            } else if is_bare_assignment(next) {
+                rest = rest[end..].trim_start();
+            } else {
+                break;
             }
-            _ => {
-                current_var.push(ch);
+        }
+    }
+}
+
+fn fold_names(statements: Vec<Statement>, same: fn(&str, &str) -> bool) -> Vec<Statement> {
+    let mut folded: Vec<Statement> = Vec::with_capacity(statements.len());
+    for statement in statements {
+        let Statement::Set(export) = statement else {
+            folded.push(statement);
+            continue;
+        };
+        let earlier = folded.iter().position(|s| matches!(s, Statement::Set(e) if same(&e.name, &export.name)));
+        match earlier {
+            Some(index) => {
+                folded.retain(|s| !matches!(s, Statement::Unset(name) if same(name, &export.name)));
+                if let Statement::Set(earlier) = &mut folded[index] {
+                    *earlier = Export { name: std::mem::take(&mut earlier.name), ..export };
+                }
             }
+            None => folded.push(Statement::Set(export)),
         }
     }
+    folded
+}
 
-    // handle any remaining variable
-    if !current_var.is_empty() {
-        if let Some(eq_pos) = current_var.find('=') {
-            let name = current_var[..eq_pos].to_string();
-            let value = parse_value(&current_var[eq_pos + 1..]);
-            exports.push(Export { name, value });
+// merge assignments whose names differ only by case
+fn fold_case(statements: Vec<Statement>, casing: CaseFold) -> Vec<Statement> {
+    let mut folded = fold_names(statements, |a, b| a.eq_ignore_ascii_case(b));
+    if casing == CaseFold::Upper {
+        for statement in &mut folded {
+            if let Statement::Set(export) = statement {
+                export.name.make_ascii_uppercase();
+            }
         }
     }
+    folded
 }
 
-fn parse_value(value_str: &str) -> String {
-    let trimmed = value_str.trim();
+struct Parser<'a> {
+    options: &'a ParseOptions,
+    report: ParseReport,
+    // line currently being parsed, for warnings, and where it starts in
+    // the input
+    line: usize,
+    line_start: usize,
+    current: String,
+    // where the command being parsed starts in that line
+    segment_start: usize,
+    // comment at the end of that line, when comments are kept
+    comment: Option<String>,
+    // files read by `--follow-source` so far, and how deep in them we are
+    sourced: Vec<PathBuf>,
+    depth: usize,
+    // a function header waiting for its `{`, and the braces open in the
+    // body of the function being defined
+    function_pending: bool,
+    function_braces: usize,
+    // the `case` statements open, and whether the next command starts an
+    // arm with its `pattern)`
+    case_depth: usize,
+    case_arm: bool,
+    // plain `NAME=value`s that aren't exported (yet), with the value as
+    // written, for an `export NAME` later on to export
+    locals: Vec<(String, String)>,
+}
 
-    // handle quoted values
-    if (trimmed.starts_with('"') && trimmed.ends_with('"')) ||
-       (trimmed.starts_with('\'') && trimmed.ends_with('\'')) {
-        // remove quotes and handle escaped characters
-        let unquoted = &trimmed[1..trimmed.len()-1];
+// which references `push_references` picks out
+#[derive(Clone, Copy)]
+struct Refs {
+    env: bool,
+    commands: bool,
+}
 
-        if trimmed.starts_with('"') {
-            // in double quotes, handle escape sequences
-            unquoted.replace("\\\"", "\"")
-                   .replace("\\\\", "\\")
-                   .replace("\\n", "\n")
-                   .replace("\\t", "\t")
-                   .replace("\\r", "\r")
+// split expandable text into plain text and the references in it: `$NAME`
+// and `${NAME}`, and `$(command)` or `` `command` ``; anything fancier
+// (`${NAME:-x}`, `$((1 + 2))`) stays text
+fn push_references(text: &str, refs: Refs, parts: &mut Vec<Part>) {
+    let mut rest = text;
+    while let Some(pos) = rest.find(['$', '`']) {
+        push_text(&rest[..pos], parts);
+        rest = &rest[pos..];
+        let after = &rest[1..];
+        let reference = if rest.starts_with('`') {
+            after.find('`')
+                .filter(|_| refs.commands)
+                .map(|close| (Part::Command(subexpressions(&after[..close])), close + 2))
+        } else if after.starts_with('(') && !after.starts_with("((") {
+            matching_paren(rest, 1)
+                .filter(|_| refs.commands)
+                .map(|close| (Part::Command(subexpressions(&rest[2..close])), close + 1))
+        } else if after.starts_with('{') {
+            matching_brace(rest, 1)
+                .filter(|_| refs.env)
+                .and_then(|close| Some((braced_reference(&rest[2..close])?, close + 1)))
         } else {
-            // single quotes preserve everything literally
-            unquoted.to_string()
-        }
-    } else {
-        trimmed.to_string()
-    }
-}
-
-pub fn exports_to_nushell(exports: Vec<Export>) -> String {
-    exports.into_iter()
-        .map(|export| {
-            // escape the value for Nushell if needed
-            let value = if export.value.contains(' ') ||
-                          export.value.contains('"') ||
-                          export.value.contains('\'') ||
-                          export.value.contains('$') ||
-                          export.value.contains('\\') {
-                format!("\"{}\"", export.value.replace('\\', "\\\\").replace('"', "\\\""))
-            } else if export.value.is_empty() {
-                "\"\"".to_string()
-            } else {
-                export.value
-            };
+            Some(name_prefix_len(after))
+                .filter(|&len| refs.env && len > 0)
+                .map(|len| (Part::Env(after[..len].to_string()), len + 1))
+        };
+        match reference {
+            Some((part, consumed)) => {
+                parts.push(part);
+                rest = &rest[consumed..];
+            }
+            None => {
+                push_text(&rest[..1], parts);
+                rest = after;
+            }
+        }
+    }
+    push_text(rest, parts);
+}
+
+// the part a `${...}` stands for, when it's a plain name or a default
+fn braced_reference(inner: &str) -> Option<Part> {
+    let name_len = name_prefix_len(inner);
+    if name_len == 0 {
+        return None;
+    }
+    if name_len == inner.len() {
+        return Some(Part::Env(inner.to_string()));
+    }
+    let ((op, empty), word) = parse_operator(&inner[name_len..])?;
+    if !matches!(op, '-' | '=') {
+        return None;
+    }
+    Some(Part::Default { name: inner[..name_len].to_string(), word: default_word(word), empty })
+}
+
+// the parts of the word of a `${NAME:-word}`: quotes are removed, and
+// single-quoted text is taken as written
+fn default_word(word: &str) -> Vec<Part> {
+    let refs = Refs { env: true, commands: true };
+    let mut parts = Vec::new();
+    let mut rest = word;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('\'') {
+            let end = after.find('\'').unwrap_or(after.len());
+            push_text(&after[..end], &mut parts);
+            rest = after.get(end + 1..).unwrap_or("");
+        } else if let Some(after) = rest.strip_prefix('"') {
+            let end = after.find('"').unwrap_or(after.len());
+            push_references(&after[..end], refs, &mut parts);
+            rest = after.get(end + 1..).unwrap_or("");
+        } else {
+            let end = rest.find(['\'', '"']).unwrap_or(rest.len());
+            push_references(&rest[..end], refs, &mut parts);
+            rest = &rest[end..];
+        }
+    }
+    parts
+}
 
-            format!("$env.{} = {}", export.name, value)
+// a command with the substitutions in it written as Nushell subexpressions,
+// so `dirname $(pwd)` becomes `dirname (pwd)`
+fn subexpressions(command: &str) -> String {
+    let mut parts = Vec::new();
+    push_references(command, Refs { env: false, commands: true }, &mut parts);
+    parts
+        .into_iter()
+        .map(|part| match part {
+            Part::Text(text) => text,
+            Part::Command(command) => format!("({})", command),
+            // not picked out without `env`
+            Part::Env(_) | Part::Default { .. } => String::new(),
         })
-        .collect::<Vec<_>>()
-        .join("\n")
+        .collect()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+fn push_text(text: &str, parts: &mut Vec<Part>) {
+    if text.is_empty() {
+        return;
+    }
+    match parts.last_mut() {
+        Some(Part::Text(last)) => last.push_str(text),
+        _ => parts.push(Part::Text(text.to_string())),
+    }
+}
 
-    #[test]
-    fn test_simple_export() {
-        let input = "export FOO=bar";
-        let exports = parse_posix_exports(input);
-        assert_eq!(exports.len(), 1);
-        assert_eq!(exports[0].name, "FOO");
-        assert_eq!(exports[0].value, "bar");
+// why an assignment whose quote never closes was refused
+pub const UNTERMINATED_QUOTE: &str = "unterminated quote";
+
+// why a name was refused under `strict_names`
+pub const INVALID_NAME: &str = "invalid variable name";
+
+// `declare` / `typeset` with their flags dropped: whether one of them was
+// `-x`, and the assignments after them
+fn declaration(segment: &str) -> Option<(bool, &str)> {
+    let mut rest = after_word(segment, "declare").or_else(|| after_word(segment, "typeset"))?.trim_start();
+    let mut exported = false;
+    while let Some(flags) = rest.strip_prefix('-') {
+        let (flags, after) = flags.split_once(char::is_whitespace).unwrap_or((flags, ""));
+        rest = after.trim_start();
+        if flags == "-" {
+            break;
+        }
+        exported |= flags.contains('x');
     }
+    Some((exported, rest))
+}
 
-    #[test]
-    fn test_multiple_exports_same_line() {
-        let input = "export FOO=bar && export BAZ=qux";
-        let exports = parse_posix_exports(input);
-        assert_eq!(exports.len(), 2);
-        assert_eq!(exports[0].name, "FOO");
-        assert_eq!(exports[0].value, "bar");
-        assert_eq!(exports[1].name, "BAZ");
-        assert_eq!(exports[1].value, "qux");
+// what follows a command word at the start of a segment and the blank after
+// it, a tab as much as a space
+fn after_word<'a>(segment: &'a str, word: &str) -> Option<&'a str> {
+    segment.strip_prefix(word).filter(|rest| rest.starts_with([' ', '\t']))
+}
+
+// how many files deep `source` is followed
+const MAX_SOURCE_DEPTH: usize = 16;
+
+// how many lines a quote left open (or a continued command) can carry on
+// over; past that, or at the end of the input, the line opening the quote is
+// taken on its own
+const MAX_CONTINUED_LINES: usize = 1000;
+
+// what follows the name of a `name() ...` or `function name ...`
+// definition, usually the `{` opening its body
+fn function_header(segment: &str) -> Option<&str> {
+    if let Some(rest) = after_word(segment, "function") {
+        let rest = rest.trim_start();
+        let end = rest.find(|c: char| c.is_whitespace() || c == '(').unwrap_or(rest.len());
+        if end == 0 {
+            return None;
+        }
+        let rest = rest[end..].trim_start();
+        return Some(rest.strip_prefix("()").unwrap_or(rest).trim_start());
+    }
+    let end = segment.find(|c: char| c.is_whitespace() || c == '(')?;
+    if end == 0 || segment[..end].contains(['=', '"', '\'', '$', '`']) {
+        return None;
     }
+    segment[end..].trim_start().strip_prefix("()").map(str::trim_start)
+}
 
-    #[test]
-    fn test_multiple_vars_one_export() {
-        let input = "export FOO=bar BAZ=qux";
-        let exports = parse_posix_exports(input);
-        assert_eq!(exports.len(), 2);
-        assert_eq!(exports[0].name, "FOO");
-        assert_eq!(exports[0].value, "bar");
-        assert_eq!(exports[1].name, "BAZ");
-        assert_eq!(exports[1].value, "qux");
+// what follows `source` or `.`, the file and any arguments
+fn source_target(segment: &str) -> Option<&str> {
+    after_word(segment, "source").or_else(|| after_word(segment, "."))
+}
+
+impl<'a> Parser<'a> {
+    fn new(options: &'a ParseOptions) -> Self {
+        Parser {
+            options,
+            report: ParseReport::default(),
+            line: 0,
+            line_start: 0,
+            current: String::new(),
+            segment_start: 0,
+            comment: None,
+            sourced: Vec::new(),
+            depth: 0,
+            function_pending: false,
+            function_braces: 0,
+            case_depth: 0,
+            case_arm: false,
+            locals: Vec::new(),
+        }
     }
 
-    #[test]
-    fn test_quoted_values() {
-        let input = r#"export FOO="hello world" && export BAR='single quotes'"#;
-        let exports = parse_posix_exports(input);
-        assert_eq!(exports.len(), 2);
-        assert_eq!(exports[0].name, "FOO");
-        assert_eq!(exports[0].value, "hello world");
-        assert_eq!(exports[1].name, "BAR");
-        assert_eq!(exports[1].value, "single quotes");
+    // returns how many lines the input had
+    fn parse_input(&mut self, input: &str) -> usize {
+        self.parse_lines(input.split_inclusive('\n').map(str::to_string))
     }
 
-    #[test]
-    fn test_escaped_quotes() {
-        let input = r#"export FOO="hello \"world\"""#;
-        let exports = parse_posix_exports(input);
-        assert_eq!(exports.len(), 1);
-        assert_eq!(exports[0].name, "FOO");
-        assert_eq!(exports[0].value, "hello \"world\"");
+    // handle multiline input a line at a time; a quote left open runs on
+    // into the next lines, so those are held until it closes
+    fn parse_lines(&mut self, lines: impl Iterator<Item = String>) -> usize {
+        let mut lines = lines.peekable();
+        let mut pending: Vec<String> = Vec::new();
+        // the number of the first pending line, and where it starts in the
+        // input; a line without its `\n` still had one separating it
+        let mut number = 1;
+        let mut start = 0;
+        // the quote open at the end of the pending lines, kept as they come
+        // so each line is only scanned once while it's waiting to close
+        let mut quote = None;
+        while let Some(line) = lines.next() {
+            quote = open_quote(line_text(&line), quote);
+            pending.push(line);
+            if quote.is_some() && lines.peek().is_some() && pending.len() < MAX_CONTINUED_LINES {
+                continue;
+            }
+            let texts: Vec<&str> = pending.iter().map(|line| line_text(line)).collect();
+            let mut done = 0;
+            while done < texts.len() {
+                let more = lines.peek().is_some() && texts.len() - done < MAX_CONTINUED_LINES;
+                let Some((line, used)) = logical_line(&texts[done..], more) else {
+                    break;
+                };
+                self.line = number;
+                self.line_start = start;
+                self.parse_line(&line);
+                number += used;
+                start += pending[done..done + used].iter().map(|line| line.len() + usize::from(!line.ends_with('\n'))).sum::<usize>();
+                done += used;
+            }
+            pending.drain(..done);
+            quote = pending.iter().fold(None, |quote, line| open_quote(line_text(line), quote));
+        }
+        number - 1
     }
 
-    #[test]
-    fn test_multiline_input() {
-        let input = "export FOO=bar\nexport BAZ=qux";
-        let exports = parse_posix_exports(input);
-        assert_eq!(exports.len(), 2);
-        assert_eq!(exports[0].name, "FOO");
-        assert_eq!(exports[0].value, "bar");
-        assert_eq!(exports[1].name, "BAZ");
-        assert_eq!(exports[1].value, "qux");
+    // one logical line, already joined with whatever continues it
+    fn parse_line(&mut self, line: &str) {
+        self.current = line.to_string();
+        let code = strip_comment(line);
+        self.comment = Some(line[code.len()..].trim_start_matches('#').trim())
+            .filter(|comment| self.options.keep_comments && !comment.is_empty())
+            .map(str::to_string);
+
+        // split on ;, &&, || and & to handle multiple commands on same line;
+        // what a pipe feeds only sees the output of the command before it
+        for (segment_start, segment, link) in split_commands(code) {
+            self.segment_start = segment_start;
+            let (segment, piped) = self.track_case(segment, link);
+            if piped {
+                let command = segment.trim();
+                self.warn(format!("dropped `{}`, only the first command of a pipeline is converted", command));
+                self.skip(command, "after a pipe");
+                continue;
+            }
+            let (segment, in_function) = self.track_functions(segment);
+            if in_function && !self.options.function_exports {
+                if !segment.is_empty() {
+                    self.skip(segment, "inside a function body");
+                }
+                continue;
+            }
+            let trimmed = strip_keywords(segment);
+            let trimmed = strip_wrapper_prefixes(trimmed, &self.options.strip_prefixes);
+
+            // check if this is an export command; `export` has to be a word
+            // of its own, so `export_dir=/x` is an assignment to export_dir
+            if trimmed.starts_with(':') {
+                self.parse_null_command(trimmed);
+            } else if let Some(export_content) = after_word(trimmed, "export") {
+                self.parse_export(export_content.trim());
+            } else if let Some((exported, content)) = declaration(trimmed) {
+                if exported {
+                    self.parse_export_content(content, true);
+                } else {
+                    self.skip(trimmed, "declared without -x");
+                }
+            } else if let Some(names) = after_word(trimmed, "unset") {
+                self.parse_unset(names.trim());
+            } else if let Some(content) = after_word(trimmed, "readonly") {
+                // readonly assigns without exporting
+                self.parse_export_content(content.trim(), false);
+            } else if let Some(alias_content) = after_word(trimmed, "alias").filter(|_| self.options.aliases) {
+                self.parse_alias(alias_content.trim());
+            } else if let Some(file) = source_target(trimmed).filter(|_| self.options.follow_source.is_some()) {
+                self.source_file(file);
+            } else if self.options.dotenv && is_bare_assignment(trimmed) {
+                self.parse_export_content(trimmed, false);
+            } else if is_bare_assignment(trimmed) {
+                self.remember_locals(trimmed);
+                self.skip(trimmed, "not an export");
+            } else if is_control_flow(trimmed) {
+                self.skip(trimmed, "control flow");
+            } else if !trimmed.is_empty() && !trimmed.starts_with('#') {
+                self.skip(trimmed, "not an export");
+            }
+        }
     }
 
-    #[test]
-    fn test_to_nushell() {
-        let exports = vec![
-            Export { name: "FOO".to_string(), value: "bar".to_string() },
-            Export { name: "PATH".to_string(), value: "/usr/bin:/bin".to_string() },
-            Export { name: "MESSAGE".to_string(), value: "hello world".to_string() },
-        ];
+    // follow `case` statements, skipping the header and `esac` as control
+    // flow and the `pattern)` starting each arm, so the commands in the arms
+    // are parsed; returns the rest of the segment and whether a pipe feeds it
+    fn track_case<'s>(&mut self, segment: &'s str, link: Link) -> (&'s str, bool) {
+        let mut rest = segment.trim();
+        if link == Link::CaseArm && self.case_depth > 0 {
+            self.case_arm = true;
+        }
+        // a `|` inside a pattern (`a|b)`) separates alternatives
+        let piped = link == Link::Pipe && !self.case_arm;
+        if !piped {
+            let command = strip_keywords(rest);
+            if let Some(arm) = case_header(command) {
+                self.skip(command[..command.len() - arm.len()].trim_end(), "control flow");
+                self.case_depth += 1;
+                self.case_arm = true;
+                rest = arm;
+            }
+        }
+        if self.case_depth == 0 {
+            return (rest, piped);
+        }
+        if strip_keywords(rest).split_whitespace().next() == Some("esac") {
+            self.case_depth -= 1;
+            self.case_arm = false;
+        } else if self.case_arm && !rest.is_empty() {
+            // a pattern without its `)` goes on after a `|`
+            let Some(close) = rest.find(')') else {
+                return ("", false);
+            };
+            self.case_arm = false;
+            rest = rest[close + 1..].trim_start();
+        }
+        (rest, piped)
+    }
+
+    // follow function definitions, whose exports only happen when the
+    // function is called; returns the segment without the header and braces
+    // and whether it's in a function body
+    fn track_functions<'s>(&mut self, segment: &'s str) -> (&'s str, bool) {
+        let mut rest = segment.trim();
+        if let Some(after) = function_header(rest) {
+            self.function_pending = true;
+            rest = after;
+        }
+        loop {
+            let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            match &rest[..word_end] {
+                "{" if self.function_pending || self.function_braces > 0 => {
+                    self.function_pending = false;
+                    self.function_braces += 1;
+                }
+                "}" if self.function_braces > 0 => self.function_braces -= 1,
+                // so a `then {` inside the body is counted too
+                "do" | "then" | "else" if self.function_braces > 0 => {}
+                _ => return (rest, self.function_braces > 0),
+            }
+            rest = rest[word_end..].trim_start();
+        }
+    }
+
+    // parse a `source`d file in place of the directive; each file is read
+    // at most once, which also stops a file sourcing itself
+    fn source_file(&mut self, command: &str) {
+        let Some(base) = &self.options.follow_source else {
+            return;
+        };
+        let Some(word) = self.words(command).into_iter().next() else {
+            return;
+        };
+        let name: String = split_word(&word, self.options)
+            .into_iter()
+            .map(|piece| match piece {
+                Piece::Literal(text) | Piece::Expandable(text) => text,
+            })
+            .collect();
+        let path = match name.strip_prefix("~/") {
+            Some(rest) => std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default().join(rest),
+            None => base.join(&name),
+        };
+        let key = path.canonicalize().unwrap_or_else(|_| path.clone());
+
+        if self.depth >= MAX_SOURCE_DEPTH {
+            self.warn(format!("not following source {}, nested too deeply", name));
+            return;
+        }
+        if self.sourced.contains(&key) {
+            self.warn(format!("not following source {} again", name));
+            return;
+        }
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                self.warn(format!("could not read sourced file {}: {}", name, err));
+                return;
+            }
+        };
+
+        // warnings inside the file are told by its own line numbers
+        let (line, line_start, segment_start) = (self.line, self.line_start, self.segment_start);
+        let current = std::mem::take(&mut self.current);
+        self.sourced.push(key);
+        self.depth += 1;
+        self.parse_input(&contents);
+        self.depth -= 1;
+        (self.line, self.line_start, self.segment_start, self.current) = (line, line_start, segment_start, current);
+    }
+
+    // `export` and its options: `-f` exports functions and `-n` takes the
+    // export off, neither of which has a Nushell `$env` to write, and `-p`
+    // only lists the exports; `--` ends the options
+    fn parse_export(&mut self, content: &str) {
+        let mut rest = content;
+        while rest.starts_with('-') {
+            let end = first_word_end(rest);
+            let option = &rest[..end];
+            rest = rest[end..].trim_start();
+            if option == "--" {
+                break;
+            } else if option.contains('f') {
+                return self.skip(content, "exports functions");
+            } else if option.contains('n') {
+                return self.skip(content, "removes the export");
+            } else if option.contains('p') {
+                return self.skip(content, "lists the exports");
+            }
+        }
+        self.parse_export_content(rest, true);
+    }
 
-        let nushell = exports_to_nushell(exports);
-        let expected = "$env.FOO = bar\n$env.PATH = /usr/bin:/bin\n$env.MESSAGE = \"hello world\"";
-        assert_eq!(nushell, expected);
+    // a line of only `NAME=value`s sets shell variables without exporting
+    // them; a command after them (`FOO=1 cmd`) only gets them for itself
+    fn remember_locals(&mut self, content: &str) {
+        let words = self.words(content);
+        let assignments: Option<Vec<_>> = words.iter().map(|word| word.split_once('=').filter(|(name, _)| is_name(name))).collect();
+        for (name, value) in assignments.unwrap_or_default() {
+            self.locals.retain(|(local, _)| local != name);
+            self.locals.push((name.to_string(), value.to_string()));
+        }
     }
-}
\ No newline at end of file
+
+    fn parse_export_content(&mut self, content: &str, exported: bool) {
+        for word in self.words(content) {
+            self.push_assignment(&word, exported);
+        }
+    }
+
+    // `unset A B`, or `unset -v A`; `unset -f` removes functions, which we
+    // don't convert
+    fn parse_unset(&mut self, content: &str) {
+        let mut names = content.split_whitespace().peekable();
+        match names.peek() {
+            Some(&"-f") => return self.skip(content, "unsets functions"),
+            Some(&"-v") => {
+                names.next();
+            }
+            _ => {}
+        }
+        for name in names {
+            if is_name(name) {
+                self.locals.retain(|(local, _)| local != name);
+                self.report.statements.push(Statement::Unset(name.to_string()));
+            } else {
+                self.skip(name, "not a variable name");
+            }
+        }
+    }
+
+    // `alias ll='ls -l' la=...`; the command is kept as written, minus quoting
+    fn parse_alias(&mut self, content: &str) {
+        for word in self.words(content) {
+            let Some((name, raw_command)) = word.split_once('=').filter(|(name, _)| !name.is_empty()) else {
+                self.skip(&word, "no command given");
+                continue;
+            };
+            let command = split_word(raw_command, self.options)
+                .into_iter()
+                .map(|piece| match piece {
+                    Piece::Literal(text) | Piece::Expandable(text) => text,
+                })
+                .collect();
+            self.report.statements.push(Statement::Alias { name: name.to_string(), command });
+        }
+    }
+
+    // split the rest of a command into its shell words, quotes still on
+    fn words(&self, content: &str) -> Vec<String> {
+        let mut words = Vec::new();
+        let mut current_var = String::new();
+        let mut in_quotes = false;
+        let mut quote_char = ' ';
+        let mut chars = content.char_indices();
+
+        while let Some((i, ch)) = chars.next() {
+            match ch {
+                '\\' if !in_quotes && !self.options.windows_paths => {
+                    // an escaped character never ends the word
+                    current_var.push(ch);
+                    current_var.extend(chars.next().map(|(_, c)| c));
+                }
+                '"' | '\'' | '`' if !in_quotes => {
+                    in_quotes = true;
+                    quote_char = ch;
+                    current_var.push(ch);
+                }
+                '"' | '\'' | '`' if in_quotes && ch == quote_char => {
+                    // in double quotes and backticks, an odd run of backslashes
+                    // escapes the quote; `\\` is just a backslash. single quotes
+                    // have no escapes
+                    let backslashes = current_var.len() - current_var.trim_end_matches('\\').len();
+                    if ch != '\'' && !backslashes.is_multiple_of(2) {
+                        current_var.push(ch);
+                    } else {
+                        in_quotes = false;
+                        current_var.push(ch);
+                    }
+                }
+                '$' if !in_quotes => {
+                    // keep a balanced `$(...)` / `$((...))` together so spaces
+                    // inside it don't end the word; an unclosed one is just text
+                    match content[i..].starts_with("$(").then(|| matching_paren(content, i + 1)).flatten() {
+                        Some(close) => {
+                            current_var.push_str(&content[i..=close]);
+                            chars.nth(content[i + 1..=close].chars().count() - 1);
+                        }
+                        None => current_var.push(ch),
+                    }
+                }
+                ' ' | '\t' if !in_quotes => {
+                    // end of current variable
+                    if !current_var.is_empty() {
+                        words.push(std::mem::take(&mut current_var));
+                    }
+                }
+                _ => {
+                    current_var.push(ch);
+                }
+            }
+        }
+
+        // handle any remaining variable
+        if !current_var.is_empty() {
+            words.push(current_var);
+        }
+        words
+    }
+
+    fn push_assignment(&mut self, word: &str, exported: bool) {
+        if open_quote(word, None).is_some() {
+            return self.reject(word, UNTERMINATED_QUOTE);
+        }
+        match word.find('=') {
+            Some(0) => self.reject(word, "empty variable name"),
+            Some(eq_pos) => self.assign(&word[..eq_pos], &word[eq_pos + 1..], exported),
+            None if exported && is_name(word) => {
+                // exporting a variable set earlier in the input exports its
+                // value; one the input never set has to be in the environment
+                if let Some(at) = self.locals.iter().position(|(name, _)| name == word) {
+                    let (name, value) = self.locals.remove(at);
+                    return self.assign(&name, &value, true);
+                }
+                let set_before = self.report.statements.iter().any(|statement| match statement {
+                    Statement::Set(export) => export.name == word,
+                    Statement::Reexport { name } => name == word,
+                    _ => false,
+                });
+                let ambient = self.options.ambient_env.as_ref().is_some_and(|env| env.contains_key(word));
+                if !set_before && !ambient {
+                    self.warn(format!("export {} has no value set before it in the input, so it needs {} in the environment", word, word));
+                }
+                self.report.statements.push(Statement::Reexport { name: word.to_string() });
+            }
+            None => self.skip(word, "no value assigned"),
+        }
+    }
+
+    fn assign(&mut self, name: &str, raw_value: &str, exported: bool) {
+        // Nushell manages these itself, and assigning them can break the shell
+        if self.options.reserved_names.iter().any(|reserved| reserved == name) {
+            let action = if self.options.skip_reserved { "skipped" } else { "assigning it may break Nushell" };
+            self.warn(format!("{} is managed by Nushell, {}", name, action));
+        }
+
+        if self.options.strip_prompts && PROMPT_NAMES.contains(&name) {
+            self.warn(format!("{} is a shell prompt setting Nushell doesn't use, skipped", name));
+            self.skip(name, "prompt setting");
+            return;
+        }
+
+        let name = if self.options.transliterate_names { transliterate(name) } else { name.to_string() };
+        // a shell refuses these, so the input never set them; --strict-names
+        // fails on them instead
+        if !is_valid_name(&name, &self.options.allowed_name_chars) {
+            if self.options.strict_names {
+                self.reject(&name, INVALID_NAME);
+            } else {
+                self.warn(format!("{} is not a valid variable name, skipped", name));
+                self.skip(&name, INVALID_NAME);
+            }
+            return;
+        }
+        let mut value = String::new();
+        let mut unresolved = Vec::new();
+        let mut parts = Vec::new();
+
+        // `$NAME` references are left to Nushell with --interpolate, unless
+        // they were expanded here; commands are always picked out, to be
+        // run by Nushell with --interpolate or --subst, or flagged
+        let convert_commands = self.options.interpolate || self.options.subst;
+        let references = Refs { env: self.options.interpolate && !self.options.expand, commands: true };
+
+        // quoted and escaped text is never expanded
+        let quoted = self.options.typed && raw_value.contains(['\'', '"', '\\']);
+        for piece in split_word(raw_value, self.options) {
+            match piece {
+                Piece::Expandable(text) => {
+                    let text = if self.options.expand {
+                        let statements = &self.report.statements;
+                        let ambient = self.options.ambient_env.as_ref();
+                        // the latest assignment, unless it was unset since
+                        let lookup = |var: &str| {
+                            let latest = statements.iter().rev().find_map(|statement| match statement {
+                                Statement::Set(e) if e.name == var => Some(Some(e.value.clone())),
+                                Statement::Unset(name) if name == var => Some(None),
+                                _ => None,
+                            });
+                            latest.unwrap_or_else(|| ambient?.get(var).cloned())
+                        };
+                        expand_value_tracked(&text, &lookup, &mut unresolved, ambient.is_none())
+                    } else {
+                        text
+                    };
+                    push_references(&text, references, &mut parts);
+                    value.push_str(&text);
+                }
+                Piece::Literal(text) => {
+                    push_text(&text, &mut parts);
+                    value.push_str(&text);
+                }
+            }
+        }
+        // against a whole environment, what isn't there is unset; that's an
+        // error only with --strict
+        let left = if self.options.ambient_env.is_some() { "left empty" } else { "left as written" };
+        for reference in unresolved {
+            if self.options.ambient_env.is_some() && self.options.errors == ErrorMode::Strict {
+                let plain = reference.trim_start_matches('$').trim_start_matches('{').trim_end_matches('}');
+                let what = if is_name(plain) { "undefined variable" } else { "could not expand" };
+                self.reject(&reference, &format!("{} in {}", what, name));
+            } else {
+                self.warn(format!("could not expand {} in {}, {}", reference, name, left));
+            }
+        }
+        if self.options.percent_decode {
+            value = percent_decode(&value);
+        }
+
+        let unconverted = !convert_commands && parts.iter().any(|part| matches!(part, Part::Command(_)));
+        if unconverted {
+            self.warn(format!("{} runs a command substitution, commented out for converting by hand", name));
+            parts.clear();
+        }
+
+        let comment = self.comment.clone();
+        let parts = Some(parts).filter(|parts| parts.iter().any(|part| !matches!(part, Part::Text(_))));
+        let export = Export { exported, comment, parts, unconverted, quoted, ..Export::new(name, value) };
+        self.report.statements.push(Statement::Set(export));
+    }
+
+    // `: ${NAME:=default}` assigns the default when NAME is unset (or empty,
+    // for `:=`); `:` itself does nothing, so several may be chained
+    fn parse_null_command(&mut self, content: &str) {
+        let mut rest = content.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+
+        while rest.starts_with("${") {
+            // the default may hold braces of its own, `${A:=${B:-c}}`
+            let Some(close) = matching_brace(rest, 1) else {
+                return;
+            };
+            let inner = &rest[2..close];
+            rest = rest[close + 1..].trim_start();
+
+            let Some(eq_pos) = inner.find('=') else {
+                continue;
+            };
+            let (name, only_unset) = match inner[..eq_pos].strip_suffix(':') {
+                Some(name) => (name, false),
+                None => (&inner[..eq_pos], true),
+            };
+            if !is_name(name) {
+                self.warn(format!("skipping default assignment to invalid name '{}'", name));
+                continue;
+            }
+
+            // what the variable holds here, if that's known: set earlier in
+            // the input, or in the environment it's expanded against
+            let current = self.report.statements.iter().rev().find_map(|statement| match statement {
+                Statement::Set(e) if e.name == name => Some(Some(e.value.clone())),
+                Statement::Unset(unset) if unset == name => Some(None),
+                _ => None,
+            });
+            let current = current.or_else(|| Some(self.options.ambient_env.as_ref()?.get(name).cloned()));
+            match current {
+                Some(Some(value)) if only_unset || !value.is_empty() => {}
+                Some(_) => self.assign(name, &inner[eq_pos + 1..], false),
+                // otherwise Nushell checks when the output is sourced, so a
+                // value the user already has is kept
+                None => {
+                    self.assign(name, &inner[eq_pos + 1..], false);
+                    self.make_default(name, !only_unset);
+                }
+            }
+        }
+    }
+
+    // turn the assignment just made to `name` into one that only applies
+    // when the variable is unset (or also empty, with `empty`)
+    fn make_default(&mut self, name: &str, empty: bool) {
+        let Some(Statement::Set(export)) = self.report.statements.last_mut() else {
+            return;
+        };
+        if export.name != name || export.unconverted {
+            return;
+        }
+        let word = export.parts.take().unwrap_or_else(|| {
+            let mut word = Vec::new();
+            push_text(&export.value, &mut word);
+            word
+        });
+        export.parts = Some(vec![Part::Default { name: name.to_string(), word, empty }]);
+    }
+
+    // checks that need the whole input parsed first
+    fn finish(mut self) -> ParseReport {
+        if self.function_braces > 0 {
+            self.warn("function body never closed, everything after it was skipped".to_string());
+        }
+
+        if self.options.only_exported {
+            self.report.statements.retain(|s| s.as_export().is_none_or(|export| export.exported));
+        }
+
+        if let Some(casing) = self.options.case_fold {
+            self.report.statements = fold_case(std::mem::take(&mut self.report.statements), casing);
+        } else if self.options.dedup {
+            self.report.statements = fold_names(std::mem::take(&mut self.report.statements), |a, b| a == b);
+        }
+
+        // dropped only now so they still count for expansion until the end
+        if self.options.skip_reserved {
+            let reserved = &self.options.reserved_names;
+            self.report.statements.retain(|s| s.as_export().is_none_or(|export| !reserved.contains(&export.name)));
+        }
+
+        self.report
+    }
+
+    fn warn(&mut self, message: String) {
+        self.report.warnings.push(Warning { line: self.line, message });
+    }
+
+    fn skip(&mut self, text: &str, reason: &str) {
+        self.push_skipped(text, reason, false);
+    }
+
+    fn reject(&mut self, text: &str, reason: &str) {
+        self.push_skipped(text, reason, true);
+    }
+
+    fn push_skipped(&mut self, text: &str, reason: &str, malformed: bool) {
+        // offsets into a sourced file mean nothing to the caller; the text is
+        // looked for from the start of its command, so an earlier command
+        // with the same text isn't pointed at
+        let start = self.line_start + self.segment_start;
+        let span = self.current[self.segment_start..].find(text)
+            .filter(|_| self.depth == 0)
+            .map(|pos| start + pos..start + pos + text.len());
+        self.report.skipped.push(Skipped { line: self.line, text: text.to_string(), reason: reason.to_string(), malformed, span });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_simple_export() {
+        let input = "export FOO=bar";
+        let exports = parse_posix_exports(input);
+        assert_eq!(exports.len(), 1);
+        assert_eq!(exports[0].name, "FOO");
+        assert_eq!(exports[0].value, "bar");
+    }
+
+    #[test]
+    fn test_merge_with_env() {
+        let current = vec![Export::new("HOME", "/home/me"), Export::new("EDITOR", "vi")];
+        let exports = parse_posix_exports("export EDITOR=vi\nexport FOO=bar");
+
+        let statements: Vec<_> = exports.iter().cloned().map(Statement::Set).collect();
+        let merged = merge_with_env(current.clone(), statements.clone(), MergeMode::Merged);
+        assert_eq!(merged, vec![
+            Export::new("HOME", "/home/me"),
+            Export::new("EDITOR", "vi"),
+            Export::new("FOO", "bar"),
+        ]);
+
+        let overlay = merge_with_env(current.clone(), statements, MergeMode::Overlay);
+        assert_eq!(overlay, vec![Export::new("FOO", "bar")]);
+
+        // an unset takes the variable out of the merged environment, and out
+        // of the overlay's exports before it
+        let report = parse_posix_report("export FOO=bar TMP=1\nunset EDITOR TMP", &ParseOptions::default());
+        let merged = merge_with_env(current.clone(), report.statements.clone(), MergeMode::Merged);
+        assert_eq!(merged, vec![Export::new("HOME", "/home/me"), Export::new("FOO", "bar")]);
+        let overlay = merge_with_env(current, report.statements, MergeMode::Overlay);
+        assert_eq!(overlay, vec![Export::new("FOO", "bar")]);
+    }
+
+    #[test]
+    fn test_multiple_exports_same_line() {
+        let input = "export FOO=bar && export BAZ=qux";
+        let exports = parse_posix_exports(input);
+        assert_eq!(exports.len(), 2);
+        assert_eq!(exports[0].name, "FOO");
+        assert_eq!(exports[0].value, "bar");
+        assert_eq!(exports[1].name, "BAZ");
+        assert_eq!(exports[1].value, "qux");
+    }
+
+    #[test]
+    fn test_multiple_vars_one_export() {
+        let input = "export FOO=bar BAZ=qux";
+        let exports = parse_posix_exports(input);
+        assert_eq!(exports.len(), 2);
+        assert_eq!(exports[0].name, "FOO");
+        assert_eq!(exports[0].value, "bar");
+        assert_eq!(exports[1].name, "BAZ");
+        assert_eq!(exports[1].value, "qux");
+    }
+
+    #[test]
+    fn test_quoted_values() {
+        let input = r#"export FOO="hello world" && export BAR='single quotes'"#;
+        let exports = parse_posix_exports(input);
+        assert_eq!(exports.len(), 2);
+        assert_eq!(exports[0].name, "FOO");
+        assert_eq!(exports[0].value, "hello world");
+        assert_eq!(exports[1].name, "BAR");
+        assert_eq!(exports[1].value, "single quotes");
+    }
+
+    #[test]
+    fn test_escaped_quotes() {
+        let input = r#"export FOO="hello \"world\"""#;
+        let exports = parse_posix_exports(input);
+        assert_eq!(exports.len(), 1);
+        assert_eq!(exports[0].name, "FOO");
+        assert_eq!(exports[0].value, "hello \"world\"");
+    }
+
+    #[test]
+    fn test_multiline_input() {
+        let input = "export FOO=bar\nexport BAZ=qux";
+        let exports = parse_posix_exports(input);
+        assert_eq!(exports.len(), 2);
+        assert_eq!(exports[0].name, "FOO");
+        assert_eq!(exports[0].value, "bar");
+        assert_eq!(exports[1].name, "BAZ");
+        assert_eq!(exports[1].value, "qux");
+    }
+
+    #[test]
+    fn test_quoted_value_across_lines() {
+        let exports = parse_posix_exports("export MSG=\"one\ntwo\" NEXT=1\nexport LAST='a\nb'");
+        assert_eq!(exports, vec![
+            Export::new("MSG", "one\ntwo"),
+            Export::new("NEXT", "1"),
+            Export::new("LAST", "a\nb"),
+        ]);
+    }
+
+    #[test]
+    fn test_double_quoted_backslash_newline_is_removed() {
+        let exports = parse_posix_exports("export X=\"a\\\nb\"\nexport Y=2");
+        assert_eq!(exports, vec![Export::new("X", "ab"), Export::new("Y", "2")]);
+    }
+
+    #[test]
+    fn test_unterminated_quotes_are_errors() {
+        let options = ParseOptions::default();
+        let error = try_parse_posix_exports("export A=1\nexport X=\"oops", &options).unwrap_err();
+        assert_eq!(error.to_string(), "line 2: X=\"oops: unterminated quote");
+        assert_eq!(error.span, Some(18..25));
+
+        let error = try_parse_posix_exports("export X='oops", &options).unwrap_err();
+        assert_eq!(error.to_string(), "line 1: X='oops: unterminated quote");
+
+        let exports = try_parse_posix_exports("export X=\"it's\" Y='say \"hi\"'", &options).unwrap();
+        assert_eq!(exports, vec![Export::new("X", "it's"), Export::new("Y", "say \"hi\"")]);
+    }
+
+    #[test]
+    fn test_stray_quote_before_large_input() {
+        // the quote never closes, so the line opening it is taken alone once
+        // it has carried over MAX_CONTINUED_LINES lines, and everything after
+        // it is still read
+        let count = 3 * MAX_CONTINUED_LINES;
+        let input: String = std::iter::once("echo don't\n".to_string()).chain((0..count).map(|i| format!("export V{}={}\n", i, i))).collect();
+        let report = parse_posix_report_lines(input.split_inclusive('\n').map(str::to_string), &ParseOptions::default());
+        let exports = report.exports();
+        assert_eq!(exports.len(), count);
+        assert_eq!(exports[0], Export::new("V0", "0"));
+        assert_eq!(exports[count - 1], Export::new(format!("V{}", count - 1), (count - 1).to_string()));
+        assert_eq!(report.skipped[0].line, 1);
+        assert_eq!(report.skipped[0].text, "echo don't");
+
+        // the same at the end of the input, before the cap is reached
+        let report = parse_posix_report("echo don't\nexport A=1\nexport B=2", &ParseOptions::default());
+        assert_eq!(report.exports(), vec![Export::new("A", "1"), Export::new("B", "2")]);
+    }
+
+    #[test]
+    fn test_unterminated_quote_stays_on_its_line() {
+        let input = "# don't\nexport A=\"x\nexport B=1";
+        let report = parse_posix_report(input, &ParseOptions::default());
+        assert_eq!(report.exports(), vec![Export::new("B", "1")]);
+        let errors: Vec<_> = report.errors().collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "line 2: A=\"x: unterminated quote");
+        assert_eq!(&input[errors[0].span.clone().unwrap()], "A=\"x");
+    }
+
+    #[test]
+    fn test_span_of_repeated_text() {
+        // the `=x` of the second command is pointed at, not the one in `B==x`
+        let input = "export A=1\nexport B==x; export =x";
+        let report = parse_posix_report(input, &ParseOptions::default());
+        let errors: Vec<_> = report.errors().collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span, Some(31..33));
+        assert_eq!(&input[31..33], "=x");
+    }
+
+    #[test]
+    fn test_malformed_input_is_skipped() {
+        let report = parse_posix_report("echo hi\nexport =oops GOOD=1 FL-AG\n# comment", &ParseOptions::default());
+        assert_eq!(report.exports(), vec![Export::new("GOOD", "1")]);
+        assert_eq!(report.lines, 3);
+        let skipped: Vec<_> = report.skipped.iter().map(|s| (s.line, s.text.as_str(), s.reason.as_str())).collect();
+        assert_eq!(skipped, vec![
+            (1, "echo hi", "not an export"),
+            (2, "=oops", "empty variable name"),
+            (2, "FL-AG", "no value assigned"),
+        ]);
+    }
+
+    #[test]
+    fn test_export_without_value() {
+        let report = parse_posix_report("export FOO\nexport FOO BAR=1", &ParseOptions::default());
+        assert_eq!(report.statements, vec![
+            Statement::Reexport { name: "FOO".to_string() },
+            Statement::Reexport { name: "FOO".to_string() },
+            Statement::Set(Export::new("BAR", "1")),
+        ]);
+        assert!(report.skipped.is_empty());
+        assert_eq!(report.exports(), vec![Export::new("BAR", "1")]);
+    }
+
+    #[test]
+    fn test_export_options() {
+        // functions and un-exports aren't turned into variables, and `--`
+        // ends the options
+        let input = "export -f myfn\nexport -n FOO\nexport -p\nexport -- A=1 -B\nexport -fn x";
+        let report = parse_posix_report(input, &ParseOptions::default());
+        assert_eq!(report.exports(), vec![Export::new("A", "1")]);
+        let skipped: Vec<_> = report.skipped.iter().map(|s| (s.text.as_str(), s.reason.as_str())).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                ("-f myfn", "exports functions"),
+                ("-n FOO", "removes the export"),
+                ("-p", "lists the exports"),
+                ("-B", "no value assigned"),
+                ("-fn x", "exports functions"),
+            ]
+        );
+        assert!(report.statements.iter().all(|statement| !matches!(statement, Statement::Reexport { .. })));
+    }
+
+    #[test]
+    fn test_export_of_earlier_assignment() {
+        // the value set earlier in the input is exported with the name; one
+        // never set is kept as a re-export, with a warning
+        let input = "FOO=1 BAR='a b'\nexport FOO\nexport BAR\nTMP=1 make\nexport TMP\nGONE=1; unset GONE; export GONE";
+        let report = parse_posix_report(input, &ParseOptions::default());
+        assert_eq!(report.exports(), vec![Export::new("FOO", "1"), Export::new("BAR", "a b")]);
+        let warnings: Vec<_> = report.warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "export TMP has no value set before it in the input, so it needs TMP in the environment",
+                "export GONE has no value set before it in the input, so it needs GONE in the environment",
+            ]
+        );
+
+        let mut ambient = std::collections::HashMap::new();
+        ambient.insert("TMP".to_string(), "x".to_string());
+        let options = ParseOptions { ambient_env: Some(ambient), ..Default::default() };
+        let report = parse_posix_report("export TMP", &options);
+        assert_eq!(report.statements, vec![Statement::Reexport { name: "TMP".to_string() }]);
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_one_line_for_loop() {
+        let exports = parse_posix_exports("for x in a b; do export FOO=$x; done");
+        assert_eq!(exports, vec![Export::new("FOO", "$x")]);
+
+        let options = ParseOptions { expand: true, ..Default::default() };
+        let report = parse_posix_report("for x in a b; do export FOO=$x; done", &options);
+        assert_eq!(report.exports(), vec![Export::new("FOO", "$x")]);
+        assert_eq!(report.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_comment_with_separators() {
+        let report = parse_posix_report("export FOO=bar # note && more; export NOPE=1", &ParseOptions::default());
+        assert_eq!(report.exports(), vec![Export::new("FOO", "bar")]);
+        assert!(report.skipped.is_empty());
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        assert_eq!(parse_posix_exports("export FOO=bar # sets foo"), vec![Export::new("FOO", "bar")]);
+
+        let report = parse_posix_report("# just a comment\n  # indented one\nexport A=1", &ParseOptions::default());
+        assert_eq!(report.exports(), vec![Export::new("A", "1")]);
+        assert!(report.skipped.is_empty());
+        assert!(parse_posix_exports("# export NOPE=1").is_empty());
+
+        // a `#` inside quotes, or in the middle of a word, is part of the value
+        let exports = parse_posix_exports("export URL=\"http://x#y\" TAG='a # b' ISSUE=gh#12 # done");
+        assert_eq!(exports, vec![
+            Export::new("URL", "http://x#y"),
+            Export::new("TAG", "a # b"),
+            Export::new("ISSUE", "gh#12"),
+        ]);
+    }
+
+    #[test]
+    fn test_mixed_whitespace_indentation() {
+        let exports = parse_posix_exports("\t  export FOO=bar \t\n  \texport BAZ=qux\t");
+        assert_eq!(exports, vec![Export::new("FOO", "bar"), Export::new("BAZ", "qux")]);
+
+        let report = parse_posix_report("\t export FOO=bar \t# note\n \t # only a comment \t", &ParseOptions::default());
+        assert_eq!(report.exports(), vec![Export::new("FOO", "bar")]);
+        assert!(report.skipped.is_empty());
+    }
+
+    #[test]
+    fn test_declare_and_typeset_exports() {
+        let input = "declare -x FOO=bar\ntypeset -x BAR=baz\ndeclare -gx G=1 H=2\ndeclare -r -x R=3\ndeclare -- -x=odd\ndeclare -a LIST=x\ndeclare LOCAL=1";
+        let report = parse_posix_report(input, &ParseOptions::default());
+        assert_eq!(report.exports(), vec![
+            Export::new("FOO", "bar"),
+            Export::new("BAR", "baz"),
+            Export::new("G", "1"),
+            Export::new("H", "2"),
+            Export::new("R", "3"),
+        ]);
+        let skipped: Vec<_> = report.skipped.iter().map(|s| (s.line, s.reason.as_str())).collect();
+        assert_eq!(skipped, vec![(5, "declared without -x"), (6, "declared without -x"), (7, "declared without -x")]);
+    }
+
+    #[test]
+    fn test_parse_posix_line() {
+        let input = "export A=1\nexport B=\"two\nlines\" C=3; export D=4 # d\necho hi";
+        let lines: Vec<_> = input.lines().collect();
+        assert_eq!(parse_posix_line(lines[0], &ParseOptions::default()), vec![Export::new("A", "1")]);
+        assert!(parse_posix_line(lines[3], &ParseOptions::default()).is_empty());
+
+        // the logical line the full parser joins is the same on its own
+        let joined = format!("{}\n{}", lines[1], lines[2]);
+        let all = parse_posix_exports(input);
+        assert_eq!(parse_posix_line(&joined, &ParseOptions::default()), all[1..].to_vec());
+    }
+
+    #[test]
+    fn test_unset() {
+        let report = parse_posix_report("export A=1 B=2\nunset A\nexport C=3; unset -v B C\nunset -f myfunc", &ParseOptions::default());
+        assert_eq!(report.statements, vec![
+            Statement::Set(Export::new("A", "1")),
+            Statement::Set(Export::new("B", "2")),
+            Statement::Unset("A".to_string()),
+            Statement::Set(Export::new("C", "3")),
+            Statement::Unset("B".to_string()),
+            Statement::Unset("C".to_string()),
+        ]);
+        assert_eq!(report.skipped.len(), 1);
+
+        // an unset variable no longer expands
+        let options = ParseOptions { expand: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export A=1\nunset A\nexport B=x$A", &options);
+        assert_eq!(exports[1], Export::new("B", "x$A"));
+    }
+
+    #[test]
+    fn test_readonly_assigns_without_exporting() {
+        let exports = parse_posix_exports("readonly VERSION=1.2 MODE=prod");
+        assert_eq!(exports, vec![
+            Export { exported: false, ..Export::new("VERSION", "1.2") },
+            Export { exported: false, ..Export::new("MODE", "prod") },
+        ]);
+        let options = ParseOptions { only_exported: true, ..Default::default() };
+        assert!(parse_posix_exports_with("readonly VERSION=1.2", &options).is_empty());
+    }
+
+    #[test]
+    fn test_empty_quoted_value_before_comment() {
+        let exports = parse_posix_exports("export EMPTY=\"\" # nothing here");
+        assert_eq!(exports, vec![Export::new("EMPTY", "")]);
+
+        let options = ParseOptions { keep_comments: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export EMPTY=\"\" # nothing here\nexport B=1", &options);
+        assert_eq!(exports, vec![
+            Export { comment: Some("nothing here".to_string()), ..Export::new("EMPTY", "") },
+            Export::new("B", "1"),
+        ]);
+    }
+
+    #[test]
+    fn test_escaped_backslash_before_closing_quote() {
+        let exports = parse_posix_exports(r#"export X="a\\" Y=1"#);
+        assert_eq!(exports, vec![Export::new("X", "a\\"), Export::new("Y", "1")]);
+
+        let exports = parse_posix_exports(r#"export X="a\"" Y=1"#);
+        assert_eq!(exports, vec![Export::new("X", "a\""), Export::new("Y", "1")]);
+
+        let exports = parse_posix_exports(r#"export X="a\\\"" Y=1"#);
+        assert_eq!(exports, vec![Export::new("X", "a\\\""), Export::new("Y", "1")]);
+
+        // a backslash in single quotes is literal and can't escape the quote
+        let exports = parse_posix_exports(r#"export B='\' S="'""#);
+        assert_eq!(exports, vec![Export::new("B", "\\"), Export::new("S", "'")]);
+    }
+
+    #[test]
+    fn test_backslash_continues_line() {
+        let exports = parse_posix_exports("export PATH=/a:\\\n/b:\\\n/c\nexport NEXT=1");
+        assert_eq!(exports, vec![Export::new("PATH", "/a:/b:/c"), Export::new("NEXT", "1")]);
+
+        // an escaped backslash, one in single quotes or in a comment doesn't
+        let exports = parse_posix_exports("export A=x\\\\\nexport B='y\\'\nexport C=z # \\\nexport D=1");
+        assert_eq!(exports, vec![
+            Export::new("A", "x\\"),
+            Export::new("B", "y\\"),
+            Export::new("C", "z"),
+            Export::new("D", "1"),
+        ]);
+
+        let report = parse_posix_report("export A=1 \\\n  B=2", &ParseOptions::default());
+        assert_eq!(report.exports(), vec![Export::new("A", "1"), Export::new("B", "2")]);
+        assert_eq!(report.lines, 2);
+    }
+
+    #[test]
+    fn test_trailing_operator_continues_line() {
+        let options = ParseOptions { dotenv: true, ..Default::default() };
+        let report = parse_posix_report("export A=1 &&\n  B=2 ||\n   export C=3\nexport D=$NOPE", &options);
+        assert_eq!(report.exports(), vec![
+            Export::new("A", "1"),
+            Export { exported: false, ..Export::new("B", "2") },
+            Export::new("C", "3"),
+            Export::new("D", "$NOPE"),
+        ]);
+        assert_eq!(report.lines, 4);
+
+        // an `&&` in a trailing comment doesn't continue anything
+        let exports = parse_posix_exports("export A=1 # x &&\nexport B=2");
+        assert_eq!(exports, vec![Export::new("A", "1"), Export::new("B", "2")]);
+    }
+
+    #[test]
+    fn test_aliases() {
+        let options = ParseOptions { aliases: true, ..Default::default() };
+        let report = parse_posix_report("export A=1\nalias ll='ls -l'; alias gs=git\\ status bad", &options);
+        assert_eq!(report.statements, vec![
+            Statement::Set(Export::new("A", "1")),
+            Statement::Alias { name: "ll".to_string(), command: "ls -l".to_string() },
+            Statement::Alias { name: "gs".to_string(), command: "git status".to_string() },
+        ]);
+        assert_eq!(report.skipped[0].text, "bad");
+
+        let report = parse_posix_report("alias ll='ls -l'", &ParseOptions::default());
+        assert!(report.statements.is_empty());
+        assert_eq!(report.skipped[0].reason, "not an export");
+    }
+
+    #[test]
+    fn test_builtin_and_keyword_names_are_kept() {
+        let options = ParseOptions { dotenv: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export test=1 read=2\ndo export echo=3 then=4\ndo=5", &options);
+        let pairs: Vec<_> = exports.iter().map(|e| (e.name.as_str(), e.value.as_str())).collect();
+        assert_eq!(pairs, vec![("test", "1"), ("read", "2"), ("echo", "3"), ("then", "4"), ("do", "5")]);
+        assert_eq!(crate::render::exports_to_nushell(exports[..1].to_vec()), "$env.test = \"1\"");
+    }
+
+    // a fresh directory under the system temp dir for file-based tests
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("from-posix-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_follow_source() {
+        let dir = temp_dir("source");
+        std::fs::write(dir.join("env"), "export INNER=2\n. ./env\nsource top.sh").unwrap();
+        std::fs::write(dir.join("top.sh"), "").unwrap();
+
+        let input = "export OUTER=1\nsource ./env\n. 'missing file'\nexport LAST=3";
+        let options = ParseOptions { follow_source: Some(dir.clone()), ..Default::default() };
+        let report = parse_posix_report(input, &options);
+        assert_eq!(report.exports(), vec![
+            Export::new("OUTER", "1"),
+            Export::new("INNER", "2"),
+            Export::new("LAST", "3"),
+        ]);
+        let warnings: Vec<_> = report.warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(warnings[0], "line 2: not following source ./env again");
+        assert!(warnings[1].starts_with("line 3: could not read sourced file missing file: "));
+
+        // without the flag a source line is just another command
+        assert_eq!(parse_posix_exports(input).len(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_transliterate_names() {
+        let options = ParseOptions { transliterate_names: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export APP-NAME=demo\nexport DÉJÀ_VU=1", &options);
+        assert_eq!(exports, vec![Export::new("APP_NAME", "demo"), Export::new("DEJA_VU", "1")]);
+
+        // without it they aren't names a shell would take
+        assert!(parse_posix_exports("export DÉJÀ_VU=1").is_empty());
+    }
+
+    #[test]
+    fn test_invalid_names_are_skipped() {
+        let report = parse_posix_report("export APP-NAME=demo 1ST=x _OK=1 V2=2", &ParseOptions::default());
+        assert_eq!(report.exports(), vec![Export::new("_OK", "1"), Export::new("V2", "2")]);
+        let messages: Vec<_> = report.warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "APP-NAME is not a valid variable name, skipped",
+            "1ST is not a valid variable name, skipped",
+        ]);
+        // skipped rather than broken, so --strict still converts the rest
+        assert_eq!(report.errors().count(), 0);
+        assert_eq!(report.skipped[0].reason, INVALID_NAME);
+    }
+
+    #[test]
+    fn test_expand_falls_back_to_ambient_env() {
+        let ambient = HashMap::from([("HOME".to_string(), "/home/me".to_string()), ("A".to_string(), "outer".to_string())]);
+        let options = ParseOptions { expand: true, ambient_env: Some(ambient), ..Default::default() };
+        let report = parse_posix_report("export CONFIG=${HOME}/.config\nexport A=inner B=$A C=$NOWHERE", &options);
+        let values: Vec<_> = report.exports().into_iter().map(|e| e.value).collect();
+        assert_eq!(values, vec!["/home/me/.config", "inner", "inner", ""]);
+        assert_eq!(report.warnings.len(), 1);
+
+        // single quotes keep it literal still
+        let exports = parse_posix_exports_with("export LIT='$NOWHERE' Q=\"[$NOWHERE]\"", &options);
+        assert_eq!(exports, vec![Export::new("LIT", "$NOWHERE"), Export::new("Q", "[]")]);
+    }
+
+    #[test]
+    fn test_percent_decode_values() {
+        let options = ParseOptions { percent_decode: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export DSN='user=me%20you&pw=a%2' PLAIN=x%20y", &options);
+        assert_eq!(exports, vec![Export::new("DSN", "user=me you&pw=a%2"), Export::new("PLAIN", "x y")]);
+        assert_eq!(parse_posix_exports("export PLAIN=x%20y")[0].value, "x%20y");
+    }
+
+    #[test]
+    fn test_dedup() {
+        let input = "export EDITOR=vi\nexport PAGER=less\nexport EDITOR=nvim";
+        let options = ParseOptions { dedup: true, ..Default::default() };
+        assert_eq!(
+            crate::render::exports_to_nushell(parse_posix_exports_with(input, &options)),
+            "$env.EDITOR = nvim\n$env.PAGER = less"
+        );
+        assert_eq!(parse_posix_exports(input).len(), 3);
+
+        // an unset in between no longer applies to the value kept
+        let report = parse_posix_report("export A=1\nunset A\nexport B=2\nexport A=3\nunset B", &options);
+        assert_eq!(report.statements, vec![
+            Statement::Set(Export::new("A", "3")),
+            Statement::Set(Export::new("B", "2")),
+            Statement::Unset("B".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_case_insensitive_dedup() {
+        let input = "export Path=/a\nexport EDITOR=vi\nexport PATH=/b";
+        let options = ParseOptions { case_fold: Some(CaseFold::First), ..Default::default() };
+        assert_eq!(parse_posix_exports_with(input, &options), vec![Export::new("Path", "/b"), Export::new("EDITOR", "vi")]);
+
+        let options = ParseOptions { case_fold: Some(CaseFold::Upper), ..Default::default() };
+        assert_eq!(parse_posix_exports_with(input, &options), vec![Export::new("PATH", "/b"), Export::new("EDITOR", "vi")]);
+
+        assert_eq!(parse_posix_exports(input).len(), 3);
+    }
+
+    #[test]
+    fn test_sudo_prefix() {
+        let exports = parse_posix_exports("sudo export FOO=bar");
+        assert_eq!(exports, vec![Export::new("FOO", "bar")]);
+    }
+
+    #[test]
+    fn test_wrapper_flag_values_and_assignments() {
+        // the values of a wrapper's flags and the variables `env` sets for
+        // the command go along with the wrapper
+        let input = "sudo -u root export A=1\n\
+                     sudo -E -g wheel --user=root export B=2\n\
+                     sudo -Eu root export C=3\n\
+                     sudo -uroot export D=4\n\
+                     env FOO=1 BAR=\"x y\" export E=5\n\
+                     env -i -u HOME -- export F=6\n\
+                     time -f '%e s' export G=7";
+        let exports = parse_posix_exports(input);
+        assert_eq!(
+            exports,
+            vec![
+                Export::new("A", "1"),
+                Export::new("B", "2"),
+                Export::new("C", "3"),
+                Export::new("D", "4"),
+                Export::new("E", "5"),
+                Export::new("F", "6"),
+                Export::new("G", "7"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_time_prefix() {
+        let exports = parse_posix_exports("time export FOO=bar");
+        assert_eq!(exports, vec![Export::new("FOO", "bar")]);
+    }
+
+    #[test]
+    fn test_custom_strip_prefixes() {
+        let options = ParseOptions { strip_prefixes: vec!["nohup".to_string()], ..Default::default() };
+        let exports = parse_posix_exports_with("nohup export FOO=bar && sudo export BAZ=qux", &options);
+        assert_eq!(exports, vec![Export::new("FOO", "bar")]);
+    }
+
+    #[test]
+    fn test_expand_indirect_reference() {
+        let options = ParseOptions { expand: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export ref=TARGET TARGET=hello\nexport OUT=${!ref}", &options);
+        assert_eq!(exports[2].name, "OUT");
+        assert_eq!(exports[2].value, "hello");
+    }
+
+    #[test]
+    fn test_expand_unresolved_indirect_stays_literal() {
+        let options = ParseOptions { expand: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export OUT=${!missing} LIT='${!ref}'", &options);
+        assert_eq!(exports[0].value, "${!missing}");
+        assert_eq!(exports[1].value, "${!ref}");
+    }
+
+    #[test]
+    fn test_expand_arithmetic_with_spaces() {
+        let options = ParseOptions { expand: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export N=4 X=$(( N * 2 )) Y=1", &options);
+        assert_eq!(exports.len(), 3);
+        assert_eq!(exports[1].value, "8");
+        assert_eq!(exports[2].value, "1");
+    }
+
+    #[test]
+    fn test_unbalanced_arithmetic_stays_literal() {
+        let options = ParseOptions { expand: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export X=$((1+2 Y=3", &options);
+        assert_eq!(exports.len(), 2);
+        assert_eq!(exports[0].value, "$((1+2");
+        assert_eq!(exports[1].value, "3");
+    }
+
+    #[test]
+    fn test_unresolved_reference_warns() {
+        let options = ParseOptions { expand: true, ..Default::default() };
+        let report = parse_posix_report("export A=1\nexport B=$A:$MISSING", &options);
+        assert_eq!(report.exports()[1].value, "1:$MISSING");
+        assert_eq!(report.warnings, vec![Warning {
+            line: 2,
+            message: "could not expand $MISSING in B, left as written".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_null_command_default_assignment() {
+        let exports = parse_posix_exports("  : ${EDITOR:=vim}");
+        assert_eq!(exports.len(), 1);
+        assert_eq!(exports[0].name, "EDITOR");
+        assert_eq!(exports[0].value, "vim");
+
+        // with nothing to check it against, the default is left for Nushell
+        // to apply, so an EDITOR the user has is kept
+        let rendered = crate::render::exports_to_nushell(parse_posix_exports(": ${EDITOR:=vim} ${PAGER=less}"));
+        assert_eq!(
+            rendered,
+            "$env.EDITOR = (if ($env.EDITOR? | is-empty) { \"vim\" } else { $env.EDITOR })\n\
+             $env.PAGER = ($env.PAGER? | default \"less\")"
+        );
+    }
+
+    #[test]
+    fn test_null_command_checks_ambient_env() {
+        let ambient = HashMap::from([("EDITOR".to_string(), "nano".to_string()), ("PAGER".to_string(), String::new())]);
+        let options = ParseOptions { expand: true, ambient_env: Some(ambient), ..Default::default() };
+        let exports = parse_posix_exports_with(": ${EDITOR:=vim} ${PAGER:=less} ${VISUAL:=vi}", &options);
+        assert_eq!(
+            exports,
+            vec![
+                Export { exported: false, ..Export::new("PAGER", "less") },
+                Export { exported: false, ..Export::new("VISUAL", "vi") },
+            ]
+        );
+
+        // an unset in the input counts as unset
+        let exports = parse_posix_exports("export A=1\nunset A\n: ${A:=2}");
+        assert_eq!(exports.last(), Some(&Export { exported: false, ..Export::new("A", "2") }));
+    }
+
+    #[test]
+    fn test_null_command_respects_existing_value() {
+        let exports = parse_posix_exports("export EDITOR=nano\n: : ${EDITOR:=vim} ${PAGER:=less}");
+        let pairs: Vec<_> = exports.iter().map(|e| (e.name.as_str(), e.value.as_str())).collect();
+        assert_eq!(pairs, vec![("EDITOR", "nano"), ("PAGER", "less")]);
+    }
+
+    #[test]
+    fn test_null_command_invalid_name() {
+        let report = parse_posix_report(": ${1BAD:=x}", &ParseOptions::default());
+        assert!(report.exports().is_empty());
+        assert_eq!(report.warnings[0].message, "skipping default assignment to invalid name '1BAD'");
+    }
+
+    #[test]
+    fn test_dotenv_marks_bare_assignments_unexported() {
+        let options = ParseOptions { dotenv: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export FOO=1\nBAR=2", &options);
+        assert_eq!(exports.len(), 2);
+        assert!(exports[0].exported);
+        assert_eq!(exports[1].name, "BAR");
+        assert!(!exports[1].exported);
+    }
+
+    #[test]
+    fn test_dotenv_mixes_bare_and_exported_lines() {
+        let input = "# settings\nexport FOO=1\n\nBAR=2\n   \n# BAZ=3\nQUX='a b' # inline\n";
+        let options = ParseOptions { dotenv: true, ..Default::default() };
+        let report = parse_posix_report(input, &options);
+        assert_eq!(report.exports(), vec![
+            Export::new("FOO", "1"),
+            Export { exported: false, ..Export::new("BAR", "2") },
+            Export { exported: false, ..Export::new("QUX", "a b") },
+        ]);
+        assert!(report.skipped.is_empty());
+
+        // without --dotenv only the export counts
+        assert_eq!(parse_posix_exports(input), vec![Export::new("FOO", "1")]);
+    }
+
+    #[test]
+    fn test_function_body_exports() {
+        let input = "#!/usr/bin/env bash\n\
+                     export TOP=1\n\
+                     setup() {\n\
+                     \x20 if [ -d /opt ]; then { export INNER=2; }; fi\n\
+                     }\n\
+                     function other { export OTHER=3; }\n\
+                     function last()\n\
+                     {\n\
+                     \x20 export LAST=4\n\
+                     }\n\
+                     export AFTER=5";
+        let report = parse_posix_report(input, &ParseOptions::default());
+        assert_eq!(report.exports(), vec![Export::new("TOP", "1"), Export::new("AFTER", "5")]);
+        assert!(report.skipped.iter().any(|s| s.text == "export OTHER=3"));
+        assert!(report.skipped.iter().all(|s| s.reason == "inside a function body"));
+        assert!(report.warnings.is_empty());
+
+        let options = ParseOptions { function_exports: true, ..Default::default() };
+        let names: Vec<_> = parse_posix_exports_with(input, &options).into_iter().map(|e| e.name).collect();
+        assert_eq!(names, vec!["TOP", "INNER", "OTHER", "LAST", "AFTER"]);
+
+        let report = parse_posix_report("f() {\nexport A=1", &ParseOptions::default());
+        assert!(report.exports().is_empty());
+        assert_eq!(report.warnings[0].message, "function body never closed, everything after it was skipped");
+    }
+
+    #[test]
+    fn test_only_exported_filter() {
+        let options = ParseOptions { dotenv: true, only_exported: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export FOO=1\nBAR=2\nexport BAZ=$BAR", &options);
+        assert_eq!(exports, vec![Export::new("FOO", "1"), Export::new("BAZ", "$BAR")]);
+    }
+
+    #[test]
+    fn test_expand_default_with_quotes_and_dollar() {
+        let options = ParseOptions { expand: true, ..Default::default() };
+        let exports = parse_posix_exports_with(r#"export PROMPT=${PS1:-"$ "}"#, &options);
+        assert_eq!(exports, vec![Export::new("PROMPT", "$ ")]);
+        assert_eq!(crate::render::exports_to_nushell(exports), r#"$env.PROMPT = '$ '"#);
+    }
+
+    #[test]
+    fn test_quoted_whitespace_value_preserved() {
+        let exports = parse_posix_exports(r#"export PAD="   ""#);
+        assert_eq!(exports[0].value, "   ");
+        assert_eq!(crate::render::exports_to_nushell(exports), r#"$env.PAD = "   ""#);
+    }
+
+    #[test]
+    fn test_single_quoted_backslash_t_is_literal() {
+        let exports = parse_posix_exports(r"export PAD='\t'");
+        assert_eq!(exports[0].value, r"\t");
+        assert_eq!(crate::render::exports_to_nushell(exports), r#"$env.PAD = '\t'"#);
+    }
+
+    #[test]
+    fn test_reserved_names_warn_by_default() {
+        let report = parse_posix_report("export PWD=/tmp FOO=bar", &ParseOptions::default());
+        assert_eq!(report.exports().len(), 2);
+        assert_eq!(report.warnings[0].message, "PWD is managed by Nushell, assigning it may break Nushell");
+    }
+
+    #[test]
+    fn test_skip_reserved_names() {
+        let options = ParseOptions { skip_reserved: true, ..Default::default() };
+        let report = parse_posix_report("export PWD=/tmp FOO=bar\nexport FILE_PWD=/x", &options);
+        assert_eq!(report.exports(), vec![Export::new("FOO", "bar")]);
+        assert_eq!(report.warnings.len(), 2);
+        assert_eq!(report.warnings[0].message, "PWD is managed by Nushell, skipped");
+        assert_eq!(report.warnings[1].line, 2);
+    }
+
+    #[test]
+    fn test_custom_reserved_names() {
+        let options = ParseOptions {
+            skip_reserved: true,
+            reserved_names: vec!["FOO".to_string()],
+            ..Default::default()
+        };
+        let exports = parse_posix_exports_with("export PWD=/tmp FOO=bar", &options);
+        assert_eq!(exports, vec![Export::new("PWD", "/tmp")]);
+    }
+
+    #[test]
+    fn test_unquoted_backslash_escapes() {
+        let exports = parse_posix_exports(r"export A=a\ b B=\$x C=plain");
+        let values: Vec<_> = exports.iter().map(|e| e.value.as_str()).collect();
+        assert_eq!(values, vec!["a b", "$x", "plain"]);
+    }
+
+    #[test]
+    fn test_printf_q_ansi_c_quoting() {
+        let options = ParseOptions { printf_q: true, ..Default::default() };
+        let exports = parse_posix_exports_with(r"export A=$'line\nnext' B=it\'s\ here", &options);
+        assert_eq!(exports[0].value, "line\nnext");
+        assert_eq!(exports[1].value, "it's here");
+
+        // without the flag `$'...'` is plain POSIX: a `$` then a quoted string
+        let exports = parse_posix_exports(r"export A=$'x'");
+        assert_eq!(exports[0].value, "$x");
+    }
+
+    #[test]
+    fn test_windows_paths_unquoted() {
+        let options = ParseOptions { windows_paths: true, ..Default::default() };
+        let exports = parse_posix_exports_with(r"export WINPATH=C:\Users\me", &options);
+        assert_eq!(exports[0].value, r"C:\Users\me");
+        assert_eq!(crate::render::exports_to_nushell(exports), r#"$env.WINPATH = 'C:\Users\me'"#);
+
+        // without the flag the backslashes escape the next character
+        let exports = parse_posix_exports(r"export WINPATH=C:\Users\me");
+        assert_eq!(exports[0].value, "C:Usersme");
+    }
+
+    #[test]
+    fn test_mixed_separators_long_line() {
+        let input = "export A=1; export B=2 && export C=3 || export D=4 & export E=5";
+        let exports = parse_posix_exports(input);
+        let names: Vec<_> = exports.iter().map(|e| e.name.as_str()).collect();
+        let values: Vec<_> = exports.iter().map(|e| e.value.as_str()).collect();
+        assert_eq!(names, vec!["A", "B", "C", "D", "E"]);
+        assert_eq!(values, vec!["1", "2", "3", "4", "5"]);
+    }
+
+    #[test]
+    fn test_unquoted_url_query_string() {
+        let exports = parse_posix_exports("export CALLBACK=https://x/cb?a=1&b=2 & export NEXT=1");
+        assert_eq!(exports, vec![
+            Export::new("CALLBACK", "https://x/cb?a=1&b=2"),
+            Export::new("NEXT", "1"),
+        ]);
+    }
+
+    #[test]
+    fn test_huge_single_line() {
+        // ~5MB with no newline: every pass over it has to stay linear
+        let padding = "x".repeat(40);
+        let input: String = (0..100_000).map(|i| format!("export V{}={}{}; ", i, padding, i)).collect();
+        assert!(input.len() > 5_000_000);
+
+        let report = parse_posix_report(&input, &ParseOptions::default());
+        assert_eq!(report.exports().len(), 100_000);
+        assert_eq!(report.exports()[99_999], Export::new("V99999", format!("{}99999", padding)));
+        assert_eq!(report.lines, 1);
+    }
+
+    #[test]
+    fn test_quoted_and_and_in_value() {
+        let exports = parse_posix_exports(r#"export CMD="build && test""#);
+        assert_eq!(exports.len(), 1);
+        assert_eq!(exports[0].name, "CMD");
+        assert_eq!(exports[0].value, "build && test");
+    }
+
+    #[test]
+    fn test_quoted_and_and_followed_by_separator() {
+        let exports = parse_posix_exports(r#"export CMD="build && test" && export NEXT=1"#);
+        assert_eq!(exports.len(), 2);
+        assert_eq!(exports[0].name, "CMD");
+        assert_eq!(exports[0].value, "build && test");
+        assert_eq!(exports[1].name, "NEXT");
+        assert_eq!(exports[1].value, "1");
+    }
+
+    #[test]
+    fn test_regression_and_and_inside_quotes() {
+        // naive `&&` splitting used to cut this into `A="x` and `y"`
+        for input in [r#"export A="x && y""#, "export A='x && y'"] {
+            let report = parse_posix_report(input, &ParseOptions::default());
+            assert_eq!(report.exports(), vec![Export::new("A", "x && y")]);
+            assert!(report.skipped.is_empty());
+            assert_eq!(crate::render::exports_to_nushell(report.exports()), r#"$env.A = "x && y""#);
+        }
+    }
+
+    #[test]
+    fn test_and_and_split_is_quote_aware() {
+        let exports = parse_posix_exports(r#"export MSG="x && y" && export N=1"#);
+        assert_eq!(exports, vec![Export::new("MSG", "x && y"), Export::new("N", "1")]);
+
+        // the same holds for single quotes and for quotes running over lines
+        let exports = parse_posix_exports("export MSG='x && y' && export N=1\nexport ML=\"a &&\nb\" && export M=2");
+        assert_eq!(exports, vec![
+            Export::new("MSG", "x && y"),
+            Export::new("N", "1"),
+            Export::new("ML", "a &&\nb"),
+            Export::new("M", "2"),
+        ]);
+    }
+
+    #[test]
+    fn test_spaces_around_separators() {
+        for input in [
+            "export FOO=bar   ;   export BAZ=qux",
+            "export FOO=bar\t &&  export BAZ=qux  ",
+            "export FOO=bar   &   export BAZ=qux",
+            "  export FOO=bar ; ;  ; export BAZ=qux ;",
+        ] {
+            let report = parse_posix_report(input, &ParseOptions::default());
+            assert_eq!(report.exports(), vec![Export::new("FOO", "bar"), Export::new("BAZ", "qux")], "{:?}", input);
+            assert!(report.skipped.is_empty(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_semicolon_and_or_separators() {
+        let exports = parse_posix_exports("export A=1 && export B=2; export C=3");
+        assert_eq!(exports, vec![Export::new("A", "1"), Export::new("B", "2"), Export::new("C", "3")]);
+
+        let exports = parse_posix_exports("export A=1 || export B=2;export C=3");
+        assert_eq!(exports, vec![Export::new("A", "1"), Export::new("B", "2"), Export::new("C", "3")]);
+
+        // quoted separators are part of the value
+        let exports = parse_posix_exports(r#"export PATH="a;b" OR='x || y'; export N=1"#);
+        assert_eq!(exports, vec![Export::new("PATH", "a;b"), Export::new("OR", "x || y"), Export::new("N", "1")]);
+
+        // and stay one value in Nushell, rather than ending the statement
+        assert_eq!(
+            crate::render::exports_to_nushell(exports),
+            "$env.PATH = \"a;b\"\n$env.OR = \"x || y\"\n$env.N = \"1\""
+        );
+    }
+
+    #[test]
+    fn test_lines_from_an_iterator() {
+        // quotes and continuations carry across lines handed over one at a time
+        let lines = ["export A=\"x", "y\"", "export B=1\\", "2", "export C=3"];
+        let exports = parse_posix_exports_lines(lines.into_iter().map(str::to_string));
+        assert_eq!(exports, vec![Export::new("A", "x\ny"), Export::new("B", "12"), Export::new("C", "3")]);
+
+        let report = parse_posix_report_lines(["export A=1\n", "oops\n", "export B=2\n"].into_iter().map(str::to_string), &ParseOptions::default());
+        assert_eq!(report.lines, 3);
+        assert_eq!(report.skipped[0].line, 2);
+    }
+
+    #[test]
+    fn test_equals_sign_in_value() {
+        // only the first `=` ends the name
+        let exports = parse_posix_exports("export FLAGS=--opt=1\nexport Q=\"a=b=c\"\nexport EMPTY=\nexport U=x==");
+        assert_eq!(
+            exports,
+            vec![Export::new("FLAGS", "--opt=1"), Export::new("Q", "a=b=c"), Export::new("EMPTY", ""), Export::new("U", "x==")]
+        );
+    }
+
+    #[test]
+    fn test_crlf_line_endings() {
+        // the `\r` of a CRLF ending is dropped, also inside a quote running on
+        // to the next line, while one inside the value stays
+        let input = "export FOO=bar\r\nexport Q=\"a b\"\r\nexport M=\"x\r\ny\"\r\nexport E=\"c\rd\"\r\nexport LAST=z\r";
+        assert_eq!(
+            parse_posix_exports(input),
+            vec![
+                Export::new("FOO", "bar"),
+                Export::new("Q", "a b"),
+                Export::new("M", "x\ny"),
+                Export::new("E", "c\rd"),
+                Export::new("LAST", "z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_control_flow_is_skipped() {
+        // the export inside the block is picked up, and the keywords around
+        // it are skipped as control flow rather than as failed exports
+        let input = "if [ -n \"$X\" ]; then export Y=1; fi\n\
+                     for d in /opt/*; do\n\
+                     \x20 export LAST=\"$d\"\n\
+                     done\n\
+                     while false; do :; done\n\
+                     if true\n\
+                     then\n\
+                     \x20 export A=2\n\
+                     elif false; then export B=3\n\
+                     else export C=4\n\
+                     fi";
+        let report = parse_posix_report(input, &ParseOptions::default());
+        assert_eq!(
+            report.exports(),
+            vec![Export::new("Y", "1"), Export::new("LAST", "$d"), Export::new("A", "2"), Export::new("B", "3"), Export::new("C", "4")]
+        );
+        let skipped: Vec<_> = report.skipped.iter().map(|s| (s.text.as_str(), s.reason.as_str())).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                ("if [ -n \"$X\" ]", "control flow"),
+                ("fi", "control flow"),
+                ("for d in /opt/*", "control flow"),
+                ("done", "control flow"),
+                ("while false", "control flow"),
+                ("done", "control flow"),
+                ("if true", "control flow"),
+                ("elif false", "control flow"),
+                ("fi", "control flow"),
+            ]
+        );
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_case_arms_are_parsed() {
+        // the header, patterns and `esac` are dropped and the commands in
+        // each arm are parsed, on one line or spread over several
+        let input = "case $x in a) export A=1;; b|c) export B=2; export C=3;; *) export D=$(pwd);; esac\n\
+                     case \"$TERM\" in\n\
+                     \x20 xterm*)\n\
+                     \x20   export COLOR=1\n\
+                     \x20   ;;\n\
+                     \x20 (dumb) export COLOR=0 ;&\n\
+                     esac\n\
+                     export AFTER=1 | cat";
+        let report = parse_posix_report(input, &ParseOptions::default());
+        let exports: Vec<_> = report.exports().into_iter().map(|e| (e.name, e.value)).collect();
+        let expected = [("A", "1"), ("B", "2"), ("C", "3"), ("D", "$(pwd)"), ("COLOR", "1"), ("COLOR", "0"), ("AFTER", "1")];
+        assert_eq!(exports, expected.map(|(name, value)| (name.to_string(), value.to_string())));
+        let skipped: Vec<_> = report.skipped.iter().map(|s| (s.text.as_str(), s.reason.as_str())).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                ("case $x in", "control flow"),
+                ("esac", "control flow"),
+                ("case \"$TERM\" in", "control flow"),
+                ("esac", "control flow"),
+                ("cat", "after a pipe"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tabs_and_runs_of_whitespace_between_assignments() {
+        // runs of spaces and tabs separate assignments without making empty
+        // ones, and whitespace around them is dropped; quoted runs are kept
+        let input = "export\tTAB=1\tNEXT=2\nexport   FOO=1   BAR=2   \n\t export \t A=1 \t\t B=\"x \t y\"\t \nexport\tC='a\tb'\t\t";
+        let report = parse_posix_report(input, &ParseOptions::default());
+        assert_eq!(
+            report.exports(),
+            vec![
+                Export::new("TAB", "1"),
+                Export::new("NEXT", "2"),
+                Export::new("FOO", "1"),
+                Export::new("BAR", "2"),
+                Export::new("A", "1"),
+                Export::new("B", "x \t y"),
+                Export::new("C", "a\tb"),
+            ]
+        );
+        assert!(report.skipped.is_empty());
+        assert!(report.warnings.is_empty());
+
+        // an empty value before a run of whitespace, and tabs after the other
+        // keywords
+        let input = "export E= \t F=3\nreadonly\tR=1\ndeclare\t-x\tD=2\nexport G=4\nunset\tG";
+        let report = parse_posix_report(input, &ParseOptions::default());
+        assert_eq!(
+            report.statements,
+            vec![
+                Statement::Set(Export::new("E", "")),
+                Statement::Set(Export::new("F", "3")),
+                Statement::Set(Export { exported: false, ..Export::new("R", "1") }),
+                Statement::Set(Export::new("D", "2")),
+                Statement::Set(Export::new("G", "4")),
+                Statement::Unset("G".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_names_starting_with_export() {
+        let options = ParseOptions { dotenv: true, ..Default::default() };
+        let exports = parse_posix_exports_with("export_dir=/x\nexporter=1\nexport\tREAL=2", &options);
+        assert_eq!(
+            exports,
+            vec![
+                Export { exported: false, ..Export::new("export_dir", "/x") },
+                Export { exported: false, ..Export::new("exporter", "1") },
+                Export::new("REAL", "2"),
+            ]
+        );
+
+        // without --dotenv they're not exports at all
+        let report = parse_posix_report("export_dir=/x\nexporter=1", &ParseOptions::default());
+        assert!(report.exports().is_empty());
+        assert_eq!(report.skipped.len(), 2);
+    }
+
+    #[test]
+    fn test_pipeline_tail_is_dropped() {
+        let report = parse_posix_report("export A=1 | tee log", &ParseOptions::default());
+        assert_eq!(report.statements, vec![Statement::Set(Export::new("A", "1"))]);
+        assert_eq!(report.skipped[0].text, "tee log");
+        assert_eq!(report.warnings[0].message, "dropped `tee log`, only the first command of a pipeline is converted");
+
+        // a pipe at the end of a line carries on into the next one
+        let report = parse_posix_report("export A=1 |\nexport B=2\nexport C=3", &ParseOptions::default());
+        assert_eq!(report.statements, vec![Statement::Set(Export::new("A", "1")), Statement::Set(Export::new("C", "3"))]);
+        assert_eq!(report.warnings.len(), 1);
+
+        // `||` is still a separator, as is a pipe inside quotes just text
+        let exports = parse_posix_exports("export A=1 || export B='x|y' |& cat");
+        assert_eq!(exports, vec![Export::new("A", "1"), Export::new("B", "x|y")]);
+    }
+
+    #[test]
+    fn test_null_command_with_nested_default() {
+        let exports = parse_posix_exports(": ${EDITOR:=${VISUAL:-vim}} ${PAGER:=less}");
+        let values: Vec<_> = exports.iter().map(|e| (e.name.as_str(), e.value.as_str())).collect();
+        assert_eq!(values, vec![("EDITOR", "${VISUAL:-vim}"), ("PAGER", "less")]);
+
+        let options = ParseOptions { expand: true, ambient_env: Some(HashMap::new()), ..Default::default() };
+        let exports = parse_posix_exports_with(": ${EDITOR:=${VISUAL:-vim}}", &options);
+        assert_eq!(exports, vec![Export { exported: false, ..Export::new("EDITOR", "vim") }]);
+    }
+}
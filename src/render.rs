@@ -0,0 +1,1234 @@
+use std::collections::HashMap;
+
+use crate::names::is_valid_name;
+use crate::options::{Format, QuoteStyle, RenderOptions};
+use crate::parser::{Export, ParseReport, Part, Statement};
+
+impl Format {
+    pub fn renderer(self) -> &'static dyn Renderer {
+        match self {
+            Format::Nushell => &Nushell,
+            Format::Tsv => &Tsv,
+            Format::JsonEnv => &JsonEnv,
+            Format::Make => &Make,
+        }
+    }
+}
+
+pub trait Renderer {
+    fn render(&self, exports: Vec<Export>, options: &RenderOptions) -> String;
+}
+
+pub struct Nushell;
+
+impl Renderer for Nushell {
+    fn render(&self, exports: Vec<Export>, options: &RenderOptions) -> String {
+        exports_to_nushell_with(exports, options)
+    }
+}
+
+pub struct Tsv;
+
+impl Renderer for Tsv {
+    fn render(&self, exports: Vec<Export>, options: &RenderOptions) -> String {
+        exports.into_iter()
+            .map(|export| format!("{}\t{}", tsv_escape(&prefixed(&export.name, options)), tsv_escape(&export.value)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+pub struct JsonEnv;
+
+impl Renderer for JsonEnv {
+    fn render(&self, exports: Vec<Export>, options: &RenderOptions) -> String {
+        // the last assignment wins, in the place of the first
+        let mut vars: Vec<Export> = Vec::new();
+        for export in exports {
+            match vars.iter_mut().find(|v| v.name == export.name) {
+                Some(existing) => existing.value = export.value,
+                None => vars.push(export),
+            }
+        }
+        let fields: Vec<_> = vars.iter()
+            .map(|export| format!("{}:{}", json_string(&prefixed(&export.name, options)), json_string(&export.value)))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+pub struct Make;
+
+impl Renderer for Make {
+    fn render(&self, exports: Vec<Export>, options: &RenderOptions) -> String {
+        exports.into_iter()
+            .map(|export| {
+                let export_word = if export.exported { "export " } else { "" };
+                format!("{}{} := {}", export_word, prefixed(&export.name, options), make_escape(&export.value))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+// `$` starts a Make reference and `#` a comment; a value can't hold a
+// newline, so those become spaces as a `\` line continuation would
+fn make_escape(value: &str) -> String {
+    value.replace('$', "$$")
+        .replace('#', "\\#")
+        .replace(['\n', '\r'], " ")
+}
+
+// keep every row on one line with exactly one tab in it
+fn tsv_escape(field: &str) -> String {
+    field.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+pub fn render(exports: Vec<Export>, options: &RenderOptions) -> String {
+    finish(options.format.renderer().render(exports, options), options)
+}
+
+// like `render`, but keeping aliases and the like in their place; formats
+// other than Nushell only have room for the exports
+pub fn render_statements(statements: Vec<Statement>, options: &RenderOptions) -> String {
+    if options.format != Format::Nushell {
+        let exports = statements.into_iter()
+            .filter_map(|statement| match statement {
+                Statement::Set(export) => Some(export),
+                _ => None,
+            })
+            .collect();
+        return render(exports, options);
+    }
+
+    // with --load-env the exports go in records, one for each run of them
+    // between other statements, so an `unset` still happens in its place
+    let mut exports = Vec::new();
+    let mut lines = Vec::new();
+    for statement in statements {
+        if !exports.is_empty() && !matches!(statement, Statement::Set(_)) {
+            lines.push(load_env_record(std::mem::take(&mut exports), options));
+        }
+        match statement {
+            Statement::Set(export) if options.load_env => exports.push(export),
+            Statement::Set(export) => lines.push(nushell_assignment(export, options)),
+            Statement::Alias { name, command } => lines.push(format!("alias {} = {}", name, command)),
+            Statement::Reexport { name } => {
+                lines.push(format!("$env.{} = $env.{}", env_key(&prefixed(&name, options)), env_key(&name)))
+            }
+            Statement::Unset(name) => lines.push(format!("hide-env {}", env_key(&prefixed(&name, options)))),
+        }
+    }
+    if !exports.is_empty() {
+        lines.push(load_env_record(exports, options));
+    }
+    finish(lines.join("\n"), options)
+}
+
+fn finish(output: String, options: &RenderOptions) -> String {
+    let mut output = if options.escape_for_interpolation {
+        escape_for_interpolation(&output)
+    } else {
+        output
+    };
+    if options.trailing_newline && !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+    output
+}
+
+// the whole parse as one JSON object for tools: `exports`, `warnings`,
+// `skipped` and `stats`
+pub fn report_json(report: &ParseReport) -> String {
+    let exports: Vec<_> = report.exports().iter()
+        .map(|export| {
+            let source = export.source.map(|s| s.to_string()).unwrap_or_else(|| "null".to_string());
+            format!(
+                "{{\"name\":{},\"value\":{},\"exported\":{},\"source\":{}}}",
+                json_string(&export.name), json_string(&export.value), export.exported, source
+            )
+        })
+        .collect();
+    let warnings: Vec<_> = report.warnings.iter()
+        .map(|warning| format!("{{\"line\":{},\"message\":{}}}", warning.line, json_string(&warning.message)))
+        .collect();
+    let skipped: Vec<_> = report.skipped.iter()
+        .map(|skipped| {
+            format!(
+                "{{\"line\":{},\"text\":{},\"reason\":{},\"malformed\":{}}}",
+                skipped.line, json_string(&skipped.text), json_string(&skipped.reason), skipped.malformed
+            )
+        })
+        .collect();
+    let stats = format!(
+        "{{\"lines\":{},\"exports\":{},\"warnings\":{},\"skipped\":{}}}",
+        report.lines, exports.len(), warnings.len(), skipped.len()
+    );
+
+    format!(
+        "{{\"exports\":[{}],\"warnings\":[{}],\"skipped\":[{}],\"stats\":{}}}",
+        exports.join(","), warnings.join(","), skipped.join(","), stats
+    )
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for ch in text.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// escape text so it can be pasted between the quotes of a Nushell `$"..."`
+// and come out unchanged
+fn escape_for_interpolation(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '(' => out.push_str("\\("),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// the exports as a plain name to value map, for library users; a repeated
+// name keeps its last value
+pub fn exports_to_map(exports: Vec<Export>) -> HashMap<String, String> {
+    exports.into_iter().map(|export| (export.name, export.value)).collect()
+}
+
+// the names assigned, each once, in the order first seen
+pub fn export_names(exports: &[Export]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for export in exports {
+        if !names.contains(&export.name) {
+            names.push(export.name.clone());
+        }
+    }
+    names
+}
+
+pub fn exports_to_nushell(exports: Vec<Export>) -> String {
+    exports_to_nushell_with(exports, &RenderOptions::default())
+}
+
+pub fn exports_to_nushell_with(exports: Vec<Export>, options: &RenderOptions) -> String {
+    if options.load_env {
+        return load_env_record(exports, options);
+    }
+    exports.into_iter()
+        .map(|export| nushell_assignment(export, options))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// `$env.NAME = value`, with any trailing comment
+fn nushell_assignment(export: Export, options: &RenderOptions) -> String {
+    let (value, notes) = nushell_rendered(&export, options);
+    let key = env_key(&prefixed(&export.name, options));
+    let assignment = if notes.is_empty() {
+        format!("$env.{} = {}", key, value)
+    } else {
+        format!("$env.{} = {} {} {}", key, value, options.comment_style.marker(), notes.join(", "))
+    };
+    if export.unconverted {
+        format!("{} {}", options.comment_style.marker(), assignment)
+    } else {
+        assignment
+    }
+}
+
+// one `load-env { ... }` with a field per variable; a record can't repeat a
+// key, so the last assignment wins, in the place of the first. Strings
+// written bare elsewhere are quoted here, as in `nushell_record`, since a
+// `:` in a bare word reads oddly next to the key's; numbers and bools are
+// written as they are in an assignment
+fn load_env_record(exports: Vec<Export>, options: &RenderOptions) -> String {
+    let mut vars: Vec<Export> = Vec::new();
+    for export in exports {
+        match vars.iter_mut().find(|v| v.name == export.name) {
+            Some(existing) => *existing = export,
+            None => vars.push(export),
+        }
+    }
+    if vars.is_empty() {
+        return "load-env {}".to_string();
+    }
+
+    let last = vars.len() - 1;
+    let fields: Vec<String> = vars.iter()
+        .enumerate()
+        .map(|(i, export)| {
+            let (mut value, notes) = nushell_rendered(export, options);
+            if value == export.value && typed_literal(export).is_none() {
+                value = double_quoted(&value);
+            }
+            let comma = if i < last { "," } else { "" };
+            let mut field = format!("{}: {}{}", env_key(&prefixed(&export.name, options)), value, comma);
+            if !notes.is_empty() {
+                field = format!("{} {} {}", field, options.comment_style.marker(), notes.join(", "));
+            }
+            if export.unconverted {
+                field = format!("{} {}", options.comment_style.marker(), field);
+            }
+            format!("  {}", field)
+        })
+        .collect();
+    format!("load-env {{\n{}\n}}", fields.join("\n"))
+}
+
+// the Nushell value for an export, and the notes to put in a comment after it
+fn nushell_rendered(export: &Export, options: &RenderOptions) -> (String, Vec<String>) {
+    let (value, kind) = if options.prompt_empty && export.value.is_empty() {
+        // ask for the value when the output is sourced
+        (format!("(input {})", double_quoted(&format!("{}: ", export.name))), "string")
+    } else if options.placeholders_as_null && is_placeholder(&export.value) {
+        ("null".to_string(), "nothing")
+    } else if let Some(parts) = &export.parts {
+        match parts.as_slice() {
+            // a value that's all one command or default is just its result
+            [part @ (Part::Command(_) | Part::Default { .. })] => (nushell_part(part), "string"),
+            parts => (nushell_interpolation(parts), "string"),
+        }
+    } else if options.split_paths && options.path_vars.contains(&export.name) {
+        (nushell_list(&split_path(&export.value, options.deduplicate_paths)), "list")
+    } else if options.split_vars.contains(&export.name) {
+        let items: Vec<String> = export.value.split(options.split_on).map(str::to_string).collect();
+        (nushell_list(&items), "list")
+    } else if options.parse_ls_colors && export.name == "LS_COLORS" {
+        (nushell_record(&parse_ls_colors(&export.value)), "record")
+    } else if let Some(literal) = typed_literal(export).filter(|_| options.typed) {
+        (export.value.clone(), literal.kind())
+    } else if reads_as_literal(&export.value) {
+        // Nushell would read it bare as a number (or a duration, or a bool),
+        // which only --typed asks for
+        let value = nushell_value(&export.value, options);
+        (if value == export.value { double_quoted(&value) } else { value }, "string")
+    } else {
+        (nushell_value(&export.value, options), value_kind(&export.value))
+    };
+
+    let mut notes = Vec::new();
+    if options.annotate_types {
+        notes.push(kind.to_string());
+    }
+    if let Some(index) = export.source.filter(|_| options.source_labels) {
+        notes.push(format!("source: {}", index));
+    }
+    if let Some(comment) = &export.comment {
+        notes.push(comment.clone());
+    }
+    (value, notes)
+}
+
+// the other way around, for `to posix`: `export NAME=value` lines a POSIX
+// shell can source, a bare `export NAME` where there's no value
+pub fn statements_to_posix(statements: &[Statement]) -> String {
+    statements.iter()
+        .map(|statement| match statement {
+            Statement::Set(export) => format!("export {}={}", export.name, posix_quote(&export.value)),
+            Statement::Reexport { name } => format!("export {}", name),
+            Statement::Alias { name, command } => format!("alias {}={}", name, posix_quote(command)),
+            Statement::Unset(name) => format!("unset {}", name),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// a value as one shell word: bare when nothing in it is special to the
+// shell, otherwise single-quoted with each `'` written as `'\''`
+pub fn posix_quote(value: &str) -> String {
+    let bare = !value.is_empty()
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || "_-./:,@%+=".contains(c));
+    if bare {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+// a name as written out, behind any --prefix
+pub fn prefixed(name: &str, options: &RenderOptions) -> String {
+    format!("{}{}", options.prefix, name)
+}
+
+// a name as a `$env` cell path member, or a record key: bare when it's an identifier, quoted
+// otherwise, since `$env.java.home` would be a path two deep
+fn env_key(name: &str) -> String {
+    if is_valid_name(name, "") {
+        name.to_string()
+    } else {
+        double_quoted(name)
+    }
+}
+
+// a value --typed writes as a Nushell int, float or bool
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl Literal {
+    fn kind(self) -> &'static str {
+        match self {
+            Literal::Int(_) => "int",
+            Literal::Float(_) => "float",
+            Literal::Bool(_) => "bool",
+        }
+    }
+}
+
+// the literal an unquoted value is, when it's plainly one: `8080`, `-1`,
+// `0.5`, `true` or `false`. `08080`, `1e5` and `+1` are left as strings,
+// since the shell never meant them as numbers either
+pub fn typed_literal(export: &Export) -> Option<Literal> {
+    if export.quoted || export.parts.is_some() {
+        return None;
+    }
+    let value = export.value.as_str();
+    match value {
+        "true" => return Some(Literal::Bool(true)),
+        "false" => return Some(Literal::Bool(false)),
+        _ => {}
+    }
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let unsigned = value.strip_prefix('-').unwrap_or(value);
+    let (whole, fraction) = match unsigned.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (unsigned, None),
+    };
+    if !digits(whole) || (whole.len() > 1 && whole.starts_with('0')) {
+        return None;
+    }
+    match fraction {
+        None => value.parse().ok().map(Literal::Int),
+        Some(fraction) if digits(fraction) => value.parse().ok().map(Literal::Float),
+        Some(_) => None,
+    }
+}
+
+// whether Nushell would read the value, written bare, as something other
+// than a string
+fn reads_as_literal(value: &str) -> bool {
+    matches!(value, "true" | "false" | "null")
+        || value.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-' || c == '.')
+}
+
+// what a plain value stands for, for `--annotate-types`; numbers are
+// quoted unless --typed, so they stay strings
+fn value_kind(value: &str) -> &'static str {
+    if value.starts_with('/') || value.starts_with("~/") || value.starts_with("./") {
+        "path"
+    } else {
+        "string"
+    }
+}
+
+// empty values and the stand-ins `.env.example` files use for secrets
+fn is_placeholder(value: &str) -> bool {
+    let lower = value.trim().to_ascii_lowercase();
+    lower.is_empty()
+        || matches!(lower.as_str(), "changeme" | "change-me" | "change_me" | "todo" | "xxx" | "..." | "placeholder")
+        || lower.starts_with("your-")
+        || lower.starts_with("your_")
+        || (lower.starts_with('<') && lower.ends_with('>'))
+}
+
+// split a `:`-separated path list; empty segments are dropped since Nushell
+// lists have no use for them, and with `dedup` only the first of repeated
+// segments is kept
+pub fn split_path(value: &str, dedup: bool) -> Vec<String> {
+    let mut segments: Vec<String> = Vec::new();
+    for segment in value.split(':').filter(|s| !s.is_empty()) {
+        if !dedup || !segments.iter().any(|s| s == segment) {
+            segments.push(segment.to_string());
+        }
+    }
+    segments
+}
+
+// `di=01;34:*.tar=01;31:` as (code, style) pairs; entries without a `=`
+// are dropped
+fn parse_ls_colors(value: &str) -> Vec<(String, String)> {
+    value.split(':')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(code, style)| (code.to_string(), style.to_string()))
+        .collect()
+}
+
+fn nushell_record(fields: &[(String, String)]) -> String {
+    let fields: Vec<_> = fields.iter()
+        .map(|(key, value)| {
+            let bare = !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            let key = if bare { key.clone() } else { double_quoted(key) };
+            format!("{}: {}", key, double_quoted(value))
+        })
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+fn nushell_list(items: &[String]) -> String {
+    let items: Vec<_> = items.iter().map(|item| double_quoted(item)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+// characters that stop a value from being written bare; braces and brackets
+// would make Nushell read a block, record or list, parentheses a
+// subexpression, `;` and `|` end the statement or start a pipe, a `#` could
+// start a comment and an `=` mid-word could be taken for another assignment
+const NEEDS_QUOTES: &[char] = &[
+    ' ', '"', '\'', '$', '\\', '{', '}', '#', '=', ';', '|', '(', ')', '[', ']', '`', ',', '@', '^', '<', '>',
+];
+
+// words Nushell reads as something other than a string when they're bare
+const NUSHELL_KEYWORDS: &[&str] = &[
+    "true", "false", "null", "if", "else", "let", "mut", "const", "def", "do", "match", "try", "catch", "for",
+    "while", "loop", "break", "continue", "return", "not", "and", "or", "export", "use", "module", "alias",
+    "source", "hide", "overlay", "where",
+];
+
+// render a value as a Nushell literal, quoting and escaping it if needed
+fn nushell_value(value: &str, options: &RenderOptions) -> String {
+    let has_control = value.chars().any(|c| c.is_control());
+
+    // raw strings can't hold escapes, so control characters rule them out
+    if options.quote_style == QuoteStyle::Raw && value.contains('\\') && !has_control {
+        raw_string(value)
+    } else if value.contains(['\\', '$']) && !value.contains('\'') && !has_control {
+        // single quotes take everything as written, so `C:\Users` needs no
+        // escaping
+        format!("'{}'", value)
+    } else if has_control || value.contains(NEEDS_QUOTES) || value.starts_with('-') || NUSHELL_KEYWORDS.contains(&value) {
+        double_quoted(value)
+    } else if value.is_empty() {
+        "\"\"".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+// a `$"..."` string looking the references up in `$env`, and running the
+// commands, when it's evaluated
+fn nushell_interpolation(parts: &[Part]) -> String {
+    let body: String = parts
+        .iter()
+        .map(|part| match part {
+            Part::Text(text) => escape_for_interpolation(text),
+            part => nushell_part(part),
+        })
+        .collect();
+    format!("$\"{}\"", body)
+}
+
+// a reference or command as a Nushell subexpression
+fn nushell_part(part: &Part) -> String {
+    match part {
+        Part::Text(text) => double_quoted(text),
+        Part::Env(name) => format!("($env.{})", name),
+        Part::Command(command) => format!("({})", command),
+        Part::Default { name, word, empty } => {
+            let word = match word.as_slice() {
+                [] => "\"\"".to_string(),
+                [Part::Text(text)] => double_quoted(text),
+                word => nushell_interpolation(word),
+            };
+            if *empty {
+                format!("(if ($env.{0}? | is-empty) {{ {1} }} else {{ $env.{0} }})", name, word)
+            } else {
+                format!("($env.{}? | default {})", name, word)
+            }
+        }
+    }
+}
+
+// a Nushell double-quoted string; control characters such as the ESC in
+// ANSI color codes are written as escapes so the output stays printable
+fn double_quoted(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for ch in value.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// wrap a value in a Nushell raw string, using one more `#` than the longest
+// `'#...` run inside it so the content can't close the string early
+fn raw_string(value: &str) -> String {
+    let mut hashes = 1;
+    while value.contains(&format!("'{}", "#".repeat(hashes))) {
+        hashes += 1;
+    }
+    let delim = "#".repeat(hashes);
+    format!("r{}'{}'{}", delim, value, delim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ParseOptions;
+    use crate::parser::{parse_posix_exports, parse_posix_exports_with, parse_posix_sources};
+
+    // what Nushell does to the body of a `$"..."` without any `(...)` in it
+    fn interpolate(body: &str) -> String {
+        let mut out = String::new();
+        let mut chars = body.chars();
+        while let Some(ch) = chars.next() {
+            assert_ne!(ch, '(', "unescaped interpolation in {:?}", body);
+            assert_ne!(ch, '"', "unescaped quote in {:?}", body);
+            if ch != '\\' {
+                out.push(ch);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some(c) => out.push(c),
+                None => panic!("dangling backslash in {:?}", body),
+            }
+        }
+        out
+    }
+
+    // what Nushell makes of a rendered value: a bare word, a raw string, a
+    // single-quoted or a double-quoted string
+    fn evaluate(literal: &str) -> String {
+        if let Some(body) = literal.strip_prefix('\'').and_then(|l| l.strip_suffix('\'')) {
+            assert!(!body.contains('\''), "unescapable quote in {:?}", body);
+            return body.to_string();
+        }
+        if let Some(rest) = literal.strip_prefix('r') {
+            // r#'...'# holds its body verbatim
+            let hashes = rest.len() - rest.trim_start_matches('#').len();
+            return rest[hashes + 1..rest.len() - hashes - 1].to_string();
+        }
+        let Some(body) = literal.strip_prefix('"').and_then(|l| l.strip_suffix('"')) else {
+            return literal.to_string();
+        };
+        let mut out = String::new();
+        let mut chars = body.chars();
+        while let Some(ch) = chars.next() {
+            assert_ne!(ch, '"', "unescaped quote in {:?}", body);
+            if ch != '\\' {
+                out.push(ch);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some(c @ ('"' | '\\')) => out.push(c),
+                other => panic!("unknown escape {:?} in {:?}", other, body),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_single_character_values() {
+        let exports = parse_posix_exports(r#"export Q='"' B=\\ S="'" D='$'"#);
+        let values: Vec<_> = exports.iter().map(|e| e.value.as_str()).collect();
+        assert_eq!(values, vec!["\"", "\\", "'", "$"]);
+
+        let rendered: Vec<_> = exports.iter().map(|e| nushell_value(&e.value, &RenderOptions::default())).collect();
+        assert_eq!(rendered, vec![r#""\"""#, r"'\'", r#""'""#, "'$'"]);
+        for (literal, value) in rendered.iter().zip(&values) {
+            assert_eq!(evaluate(literal), *value);
+        }
+
+        let options = RenderOptions { quote_style: QuoteStyle::Raw, ..Default::default() };
+        assert_eq!(nushell_value("\\", &options), "r#'\\'#");
+        assert_eq!(evaluate(&nushell_value("\\", &options)), "\\");
+    }
+
+    #[test]
+    fn test_quotes_and_backslash_round_trip() {
+        let exports = parse_posix_exports(r#"export X='a"b\c'"d'e""#);
+        assert_eq!(exports, vec![Export::new("X", r#"a"b\cd'e"#)]);
+
+        let nushell = exports_to_nushell(exports.clone());
+        assert_eq!(nushell, r#"$env.X = "a\"b\\cd'e""#);
+        assert_eq!(evaluate(nushell.strip_prefix("$env.X = ").unwrap()), exports[0].value);
+
+        let raw = RenderOptions { quote_style: QuoteStyle::Raw, ..Default::default() };
+        let nushell = exports_to_nushell_with(exports.clone(), &raw);
+        assert_eq!(nushell, r#"$env.X = r#'a"b\cd'e'#"#);
+        assert_eq!(evaluate(nushell.strip_prefix("$env.X = ").unwrap()), exports[0].value);
+    }
+
+    #[test]
+    fn test_to_nushell() {
+        let exports = vec![
+            Export::new("FOO", "bar"),
+            Export::new("PATH", "/usr/bin:/bin"),
+            Export::new("MESSAGE", "hello world"),
+            Export::new("WINPATH", r"C:\Users\me"),
+            Export::new("PRICE", "$5"),
+            Export::new("QUOTES", r#"it's "$5" \o/"#),
+        ];
+
+        let nushell = exports_to_nushell(exports);
+        let expected = concat!(
+            "$env.FOO = bar\n$env.PATH = /usr/bin:/bin\n$env.MESSAGE = \"hello world\"\n",
+            r"$env.WINPATH = 'C:\Users\me'", "\n",
+            "$env.PRICE = '$5'\n",
+            r#"$env.QUOTES = "it's \"$5\" \\o/""#,
+        );
+        assert_eq!(nushell, expected);
+    }
+
+    #[test]
+    fn test_raw_quote_style_windows_path() {
+        let exports = vec![Export::new("WINPATH", r"C:\Users\me")];
+        let options = RenderOptions { quote_style: QuoteStyle::Raw, ..Default::default() };
+        assert_eq!(exports_to_nushell_with(exports, &options), r"$env.WINPATH = r#'C:\Users\me'#");
+    }
+
+    #[test]
+    fn test_raw_quote_style_extra_hashes() {
+        let exports = vec![Export::new("RE", r"a\d'#b")];
+        let options = RenderOptions { quote_style: QuoteStyle::Raw, ..Default::default() };
+        assert_eq!(exports_to_nushell_with(exports, &options), r"$env.RE = r##'a\d'#b'##");
+    }
+
+    #[test]
+    fn test_source_labels() {
+        let sources = vec![
+            "export FOO=1\nexport BAR=2".to_string(),
+            "export FOO=3".to_string(),
+        ];
+        let exports = parse_posix_sources(&sources, &ParseOptions::default()).into_exports();
+        let options = RenderOptions { source_labels: true, ..Default::default() };
+        let expected = "$env.FOO = \"1\" # source: 0\n$env.BAR = \"2\" # source: 0\n$env.FOO = \"3\" # source: 1";
+        assert_eq!(exports_to_nushell_with(exports, &options), expected);
+    }
+
+    #[test]
+    fn test_escape_control_characters() {
+        let exports = vec![
+            Export::new("GREEN", "\x1b[0;32m"),
+            Export::new("MULTI", "a\nb"),
+        ];
+        let nushell = exports_to_nushell(exports);
+        assert_eq!(nushell, "$env.GREEN = \"\\u{1b}[0;32m\"\n$env.MULTI = \"a\\nb\"");
+        assert!(!nushell.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_raw_quote_style_falls_back_for_control_characters() {
+        let exports = vec![Export::new("PS1", "\\w\x1b[0m")];
+        let options = RenderOptions { quote_style: QuoteStyle::Raw, ..Default::default() };
+        assert_eq!(exports_to_nushell_with(exports, &options), "$env.PS1 = \"\\\\w\\u{1b}[0m\"");
+    }
+
+    #[test]
+    fn test_escape_for_interpolation_round_trips() {
+        let exports = vec![
+            Export::new("MSG", "say \"hi\" (now)"),
+            Export::new("WIN", r"C:\temp"),
+        ];
+        let plain = exports_to_nushell(exports.clone());
+        let options = RenderOptions { escape_for_interpolation: true, ..Default::default() };
+        let escaped = render(exports, &options);
+        assert_eq!(escaped, r#"$env.MSG = \"say \\\"hi\\\" \(now)\"\n$env.WIN = 'C:\\temp'"#);
+        assert_eq!(interpolate(&escaped), plain);
+    }
+
+    #[test]
+    fn test_interpolate_references() {
+        let options = ParseOptions { interpolate: true, ..Default::default() };
+        let input = "export PATH=$PATH:/bin\n\
+                     export URL=\"${PROTO}://$HOST:${PORT}/(api)\"\n\
+                     export LIT='$HOME' MIX=\"$HOME\"'/$x' PRICE=5$ DEF=${A:-b}";
+        assert_eq!(
+            exports_to_nushell(parse_posix_exports_with(input, &options)),
+            "$env.PATH = $\"($env.PATH):/bin\"\n\
+             $env.URL = $\"($env.PROTO)://($env.HOST):($env.PORT)/\\(api)\"\n\
+             $env.LIT = '$HOME'\n\
+             $env.MIX = $\"($env.HOME)/$x\"\n\
+             $env.PRICE = '5$'\n\
+             $env.DEF = (if ($env.A? | is-empty) { \"b\" } else { $env.A })"
+        );
+
+        // --expand resolves them instead
+        let options = ParseOptions { interpolate: true, expand: true, ..Default::default() };
+        assert_eq!(exports_to_nushell(parse_posix_exports_with("export A=1 B=$A", &options)), "$env.A = \"1\"\n$env.B = \"1\"");
+    }
+
+    #[test]
+    fn test_very_long_name_with_empty_value() {
+        let name = "A".repeat(10_000);
+        let started = std::time::Instant::now();
+        let exports = parse_posix_exports(&format!("export {}=", name));
+        assert_eq!(exports, vec![Export::new(name.clone(), "")]);
+        assert_eq!(exports_to_nushell(exports), format!("$env.{} = \"\"", name));
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_split_paths() {
+        let exports = vec![Export::new("PATH", "/usr/bin:/bin"), Export::new("EDITOR", "vi")];
+        let options = RenderOptions { split_paths: true, ..Default::default() };
+        assert_eq!(
+            exports_to_nushell_with(exports, &options),
+            "$env.PATH = [\"/usr/bin\", \"/bin\"]\n$env.EDITOR = vi"
+        );
+    }
+
+    #[test]
+    fn test_split_paths_drops_empty_segments() {
+        let exports = vec![Export::new("PATH", ":/usr/bin::/bin:"), Export::new("MANPATH", "")];
+        let options = RenderOptions { split_paths: true, ..Default::default() };
+        assert_eq!(
+            exports_to_nushell_with(exports, &options),
+            "$env.PATH = [\"/usr/bin\", \"/bin\"]\n$env.MANPATH = []"
+        );
+    }
+
+    #[test]
+    fn test_split_paths_only_splits_path_vars() {
+        let exports = vec![Export::new("PYTHONPATH", "/a:/b"), Export::new("URL", "http://host:80")];
+        let options = RenderOptions { split_paths: true, path_vars: vec!["PYTHONPATH".to_string()], ..Default::default() };
+        assert_eq!(
+            exports_to_nushell_with(exports.clone(), &options),
+            "$env.PYTHONPATH = [\"/a\", \"/b\"]\n$env.URL = http://host:80"
+        );
+
+        let options = RenderOptions { split_paths: true, ..Default::default() };
+        assert_eq!(
+            exports_to_nushell_with(exports, &options),
+            "$env.PYTHONPATH = /a:/b\n$env.URL = http://host:80"
+        );
+    }
+
+    #[test]
+    fn test_parse_ls_colors() {
+        let exports = vec![Export::new("LS_COLORS", "di=01;34:ln=01;36:*.tar=01;31:")];
+        let options = RenderOptions { parse_ls_colors: true, ..Default::default() };
+        assert_eq!(
+            exports_to_nushell_with(exports, &options),
+            "$env.LS_COLORS = {di: \"01;34\", ln: \"01;36\", \"*.tar\": \"01;31\"}"
+        );
+    }
+
+    #[test]
+    fn test_annotate_types() {
+        let exports = vec![
+            Export::new("PORT", "8080"),
+            Export { source: Some(1), ..Export::new("PATH", "/usr/bin:/bin") },
+            Export::new("CONFIG", "/etc/app.conf"),
+            Export::new("EDITOR", "vi"),
+        ];
+        let options = RenderOptions { annotate_types: true, split_paths: true, source_labels: true, ..Default::default() };
+        assert_eq!(
+            exports_to_nushell_with(exports, &options),
+            "$env.PORT = \"8080\" # string\n\
+             $env.PATH = [\"/usr/bin\", \"/bin\"] # list, source: 1\n\
+             $env.CONFIG = /etc/app.conf # path\n\
+             $env.EDITOR = vi # string"
+        );
+    }
+
+    #[test]
+    fn test_kept_comment() {
+        let exports = vec![Export { comment: Some("nothing here".to_string()), ..Export::new("EMPTY", "") }];
+        assert_eq!(exports_to_nushell(exports.clone()), "$env.EMPTY = \"\" # nothing here");
+        let options = RenderOptions { annotate_types: true, ..Default::default() };
+        assert_eq!(exports_to_nushell_with(exports, &options), "$env.EMPTY = \"\" # string, nothing here");
+    }
+
+    #[test]
+    fn test_split_vars() {
+        let exports = vec![Export::new("TAGS", "a,b,c"), Export::new("NAME", "x,y")];
+        let options = RenderOptions { split_vars: vec!["TAGS".to_string()], ..Default::default() };
+        assert_eq!(
+            exports_to_nushell_with(exports, &options),
+            "$env.TAGS = [\"a\", \"b\", \"c\"]\n$env.NAME = \"x,y\""
+        );
+
+        let exports = vec![Export::new("HOSTS", "a;;b")];
+        let options = RenderOptions { split_on: ';', split_vars: vec!["HOSTS".to_string()], ..Default::default() };
+        assert_eq!(exports_to_nushell_with(exports, &options), "$env.HOSTS = [\"a\", \"\", \"b\"]");
+    }
+
+    #[test]
+    fn test_deduplicate_paths() {
+        let exports = vec![Export::new("PATH", "/usr/bin:/usr/local/bin:/usr/bin:/bin:/usr/local/bin")];
+        let options = RenderOptions { split_paths: true, deduplicate_paths: true, ..Default::default() };
+        assert_eq!(
+            exports_to_nushell_with(exports, &options),
+            "$env.PATH = [\"/usr/bin\", \"/usr/local/bin\", \"/bin\"]"
+        );
+    }
+
+    #[test]
+    fn test_placeholders_as_null() {
+        let exports = vec![
+            Export::new("API_KEY", ""),
+            Export::new("SECRET", "your-key-here"),
+            Export::new("HOST", "<hostname>"),
+            Export::new("PORT", "8080"),
+        ];
+        let options = RenderOptions { placeholders_as_null: true, ..Default::default() };
+        assert_eq!(
+            exports_to_nushell_with(exports, &options),
+            "$env.API_KEY = null\n$env.SECRET = null\n$env.HOST = null\n$env.PORT = \"8080\""
+        );
+    }
+
+    #[test]
+    fn test_prompt_empty() {
+        let exports = vec![Export::new("API_KEY", ""), Export::new("HOST", "localhost")];
+        let options = RenderOptions { prompt_empty: true, placeholders_as_null: true, ..Default::default() };
+        assert_eq!(
+            exports_to_nushell_with(exports, &options),
+            "$env.API_KEY = (input \"API_KEY: \")\n$env.HOST = localhost"
+        );
+    }
+
+    #[test]
+    fn test_hash_is_quoted() {
+        let exports = parse_posix_exports("export TAG=v1#stable PLAIN=v1-stable");
+        assert_eq!(exports_to_nushell(exports), "$env.TAG = \"v1#stable\"\n$env.PLAIN = v1-stable");
+
+        // alongside the other characters Nushell would read as syntax
+        let exports = parse_posix_exports(r#"export A='x#y;z' B='#[a]' C="(a) #b|c" D='`#`,@^<>'"#);
+        assert_eq!(
+            exports_to_nushell(exports),
+            "$env.A = \"x#y;z\"\n$env.B = \"#[a]\"\n$env.C = \"(a) #b|c\"\n$env.D = \"`#`,@^<>\""
+        );
+    }
+
+    #[test]
+    fn test_leading_dash_is_quoted() {
+        // a bare `--verbose` is read as a flag; `-5` is quoted too, so it
+        // stays the string the shell had
+        let exports = parse_posix_exports("export OFFSET=-5 FLAG=--verbose SHORT=-x DASH=-");
+        assert_eq!(
+            exports_to_nushell(exports.clone()),
+            "$env.OFFSET = \"-5\"\n$env.FLAG = \"--verbose\"\n$env.SHORT = \"-x\"\n$env.DASH = \"-\""
+        );
+
+        let options = RenderOptions { annotate_types: true, ..Default::default() };
+        let output = exports_to_nushell_with(exports[..2].to_vec(), &options);
+        assert_eq!(output, "$env.OFFSET = \"-5\" # string\n$env.FLAG = \"--verbose\" # string");
+    }
+
+    // undo `double_quoted`, as Nushell reads the string back
+    fn read_double_quoted(rendered: &str) -> String {
+        let body = rendered.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')).unwrap();
+        let mut out = String::new();
+        let mut chars = body.chars();
+        while let Some(ch) = chars.next() {
+            if ch == '\\' {
+                out.push(chars.next().unwrap());
+            } else {
+                out.push(ch);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_nushell_metacharacters_are_quoted() {
+        // each of these would be read as another statement, a pipe, a list,
+        // a subexpression or some other syntax if written bare
+        let values = [
+            "a;b", "a|b", "[a]", "`x`", "(cmd)", "a,b", "user@host", "^ls", "a<b", "a>b", "-5", "--opt",
+            "true", "false", "null", "if", "let",
+        ];
+        for value in values {
+            let input = format!("export V='{}'", value);
+            let rendered = exports_to_nushell(parse_posix_exports(&input));
+            let literal = rendered.strip_prefix("$env.V = ").unwrap();
+            assert_eq!(literal, double_quoted(value), "{}", input);
+            assert_eq!(read_double_quoted(literal), value, "{}", input);
+        }
+
+        // words that only contain a keyword stay bare
+        assert_eq!(exports_to_nushell(parse_posix_exports("export V=iffy W=nullable")), "$env.V = iffy\n$env.W = nullable");
+    }
+
+    #[test]
+    fn test_odd_names_are_quoted() {
+        let exports = vec![Export::new("FOO.BAR", "1"), Export::new("APP-NAME", "demo"), Export::new("OK_1", "x")];
+        assert_eq!(
+            exports_to_nushell(exports),
+            "$env.\"FOO.BAR\" = \"1\"\n$env.\"APP-NAME\" = demo\n$env.OK_1 = x"
+        );
+    }
+
+    #[test]
+    fn test_braces_are_quoted() {
+        let exports = vec![
+            Export::new("TPL", "{foo}"),
+            Export::new("EMPTY", "{}"),
+            Export::new("MID", "a{b}c"),
+        ];
+        assert_eq!(
+            exports_to_nushell(exports),
+            "$env.TPL = \"{foo}\"\n$env.EMPTY = \"{}\"\n$env.MID = \"a{b}c\""
+        );
+    }
+
+    #[test]
+    fn test_report_json() {
+        let report = ParseReport {
+            statements: vec![Statement::Set(Export { source: Some(0), ..Export::new("MSG", "say \"hi\"\n") })],
+            warnings: vec![],
+            skipped: vec![crate::parser::Skipped {
+                line: 2,
+                text: "FLAG".to_string(),
+                reason: "no value assigned".to_string(),
+                malformed: false,
+                span: None,
+            }],
+            lines: 2,
+        };
+        assert_eq!(
+            report_json(&report),
+            concat!(
+                "{\"exports\":[{\"name\":\"MSG\",\"value\":\"say \\\"hi\\\"\\n\",\"exported\":true,\"source\":0}],",
+                "\"warnings\":[],",
+                "\"skipped\":[{\"line\":2,\"text\":\"FLAG\",\"reason\":\"no value assigned\",\"malformed\":false}],",
+                "\"stats\":{\"lines\":2,\"exports\":1,\"warnings\":0,\"skipped\":1}}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_env_format() {
+        let exports = vec![Export::new("A", "1"), Export::new("MSG", "say \"hi\""), Export::new("A", "2")];
+        let options = RenderOptions { format: Format::JsonEnv, ..Default::default() };
+        assert_eq!(render(exports, &options), r#"{"A":"2","MSG":"say \"hi\""}"#);
+    }
+
+    #[test]
+    fn test_make_format() {
+        let exports = vec![
+            Export::new("PRICE", "$5 or ${COST}"),
+            Export { exported: false, ..Export::new("TAG", "v1 # latest") },
+            Export::new("LINES", "a\nb"),
+        ];
+        let options = RenderOptions { format: Format::Make, ..Default::default() };
+        assert_eq!(
+            render(exports, &options),
+            "export PRICE := $$5 or $${COST}\nTAG := v1 \\# latest\nexport LINES := a b"
+        );
+    }
+
+    #[test]
+    fn test_exports_to_map() {
+        let exports = vec![Export::new("A", "1"), Export::new("B", "2"), Export::new("A", "3")];
+        let map = exports_to_map(exports);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["A"], "3");
+        assert_eq!(map["B"], "2");
+    }
+
+    #[test]
+    fn test_unset_renders_hide_env() {
+        let statements = vec![
+            Statement::Set(Export::new("A", "1")),
+            Statement::Unset("OLD".to_string()),
+            Statement::Unset("OTHER".to_string()),
+            Statement::Set(Export::new("B", "2")),
+        ];
+        assert_eq!(
+            render_statements(statements, &RenderOptions::default()),
+            "$env.A = \"1\"\nhide-env OLD\nhide-env OTHER\n$env.B = \"2\""
+        );
+    }
+
+    #[test]
+    fn test_render_statements_keeps_aliases_in_order() {
+        let statements = vec![
+            Statement::Set(Export::new("EDITOR", "vim")),
+            Statement::Alias { name: "ll".to_string(), command: "ls -l".to_string() },
+            Statement::Set(Export::new("PAGER", "less")),
+        ];
+        assert_eq!(
+            render_statements(statements.clone(), &RenderOptions::default()),
+            "$env.EDITOR = vim\nalias ll = ls -l\n$env.PAGER = less"
+        );
+
+        let tsv = RenderOptions { format: Format::Tsv, ..Default::default() };
+        assert_eq!(render_statements(statements, &tsv), "EDITOR\tvim\nPAGER\tless");
+    }
+
+    #[test]
+    fn test_tsv_format() {
+        let exports = vec![
+            Export::new("FOO", "bar"),
+            Export::new("COLS", "a\tb"),
+        ];
+        let options = RenderOptions { format: Format::Tsv, ..Default::default() };
+        assert_eq!(render(exports, &options), "FOO\tbar\nCOLS\ta\\tb");
+    }
+
+    #[test]
+    fn test_load_env_record() {
+        let exports = vec![
+            Export::new("FOO", "bar"),
+            Export::new("PATH", "/usr/bin:/bin"),
+            Export::new("GREETING", "hello world"),
+            Export::new("FOO", "baz"),
+        ];
+        let options = RenderOptions { load_env: true, ..Default::default() };
+        assert_eq!(
+            exports_to_nushell_with(exports, &options),
+            "load-env {\n  FOO: \"baz\",\n  PATH: \"/usr/bin:/bin\",\n  GREETING: \"hello world\"\n}"
+        );
+        assert_eq!(exports_to_nushell_with(Vec::new(), &options), "load-env {}");
+    }
+
+    #[test]
+    fn test_load_env_keeps_other_statements_in_order() {
+        let statements = vec![
+            Statement::Set(Export::new("A", "1")),
+            Statement::Alias { name: "ll".to_string(), command: "ls -l".to_string() },
+            Statement::Set(Export::new("B", "x")),
+        ];
+        let options = RenderOptions { load_env: true, ..Default::default() };
+        assert_eq!(
+            render_statements(statements, &options),
+            "load-env {\n  A: \"1\"\n}\nalias ll = ls -l\nload-env {\n  B: \"x\"\n}"
+        );
+
+        // an unset before the export doesn't hide it afterwards
+        let statements = vec![Statement::Unset("A".to_string()), Statement::Set(Export::new("A", "1"))];
+        assert_eq!(render_statements(statements, &options), "hide-env A\nload-env {\n  A: \"1\"\n}");
+    }
+
+    #[test]
+    fn test_equals_sign_in_value() {
+        let exports = parse_posix_exports("export FLAGS=--opt=1\nexport Q=\"a=b=c\"\nexport EMPTY=");
+        assert_eq!(exports_to_nushell(exports), "$env.FLAGS = \"--opt=1\"\n$env.Q = \"a=b=c\"\n$env.EMPTY = \"\"");
+    }
+
+    #[test]
+    fn test_dollar_values_are_single_quoted() {
+        // a literal `$` stays literal, backslashes included, unless a `'` or a
+        // control character rules single quotes out
+        let input = "export PRICE='$5'\nexport VAR=a\\$b\nexport WIN='C:\\$x'\nexport BOTH=\"it's \\$5\"";
+        assert_eq!(
+            exports_to_nushell(parse_posix_exports(input)),
+            "$env.PRICE = '$5'\n$env.VAR = 'a$b'\n$env.WIN = 'C:\\$x'\n$env.BOTH = \"it's $5\""
+        );
+    }
+
+    #[test]
+    fn test_statements_to_posix() {
+        let statements = vec![
+            Statement::Set(Export::new("FOO", "bar")),
+            Statement::Set(Export::new("MESSAGE", "hello world")),
+            Statement::Set(Export::new("QUOTE", "it's $5")),
+            Statement::Set(Export::new("EMPTY", "")),
+            Statement::Reexport { name: "EDITOR".to_string() },
+        ];
+        assert_eq!(
+            statements_to_posix(&statements),
+            "export FOO=bar\nexport MESSAGE='hello world'\nexport QUOTE='it'\\''s $5'\nexport EMPTY=''\nexport EDITOR"
+        );
+
+        // and back again
+        let input = statements_to_posix(&statements);
+        let exports = parse_posix_exports(&input);
+        assert_eq!(
+            exports,
+            vec![Export::new("FOO", "bar"), Export::new("MESSAGE", "hello world"), Export::new("QUOTE", "it's $5"), Export::new("EMPTY", "")]
+        );
+    }
+
+    #[test]
+    fn test_numbers_are_quoted_without_typed() {
+        // written bare, Nushell would read these as an int, a float and a
+        // duration
+        let input = "export PORT=\"8080\" N=8080 RATIO=0.5 WAIT=10s PRICE=5$ V=1.2.3";
+        assert_eq!(
+            exports_to_nushell(parse_posix_exports(input)),
+            "$env.PORT = \"8080\"\n$env.N = \"8080\"\n$env.RATIO = \"0.5\"\n$env.WAIT = \"10s\"\n$env.PRICE = '5$'\n$env.V = \"1.2.3\""
+        );
+    }
+
+    #[test]
+    fn test_typed_values() {
+        let parse_options = ParseOptions { typed: true, ..Default::default() };
+        let options = RenderOptions { typed: true, annotate_types: true, ..Default::default() };
+        let input = "export PORT=8080 RATIO=0.5 DEBUG=true QUIET=false QUOTED=\"8080\" ZIP=08080 OFF=-1 V=1.2.3 NAME=web";
+        assert_eq!(
+            exports_to_nushell_with(parse_posix_exports_with(input, &parse_options), &options),
+            "$env.PORT = 8080 # int\n\
+             $env.RATIO = 0.5 # float\n\
+             $env.DEBUG = true # bool\n\
+             $env.QUIET = false # bool\n\
+             $env.QUOTED = \"8080\" # string\n\
+             $env.ZIP = \"08080\" # string\n\
+             $env.OFF = -1 # int\n\
+             $env.V = \"1.2.3\" # string\n\
+             $env.NAME = web # string"
+        );
+    }
+
+    #[test]
+    fn test_prefix() {
+        let input = "export PORT=8080 HOST=localhost";
+        let plain = exports_to_nushell(parse_posix_exports(input));
+        let options = RenderOptions { prefix: String::new(), ..Default::default() };
+        assert_eq!(exports_to_nushell_with(parse_posix_exports(input), &options), plain);
+
+        let options = RenderOptions { prefix: "APP_".to_string(), ..Default::default() };
+        assert_eq!(
+            exports_to_nushell_with(parse_posix_exports(input), &options),
+            "$env.APP_PORT = \"8080\"\n$env.APP_HOST = localhost"
+        );
+
+        // the variable a bare export re-exports is still the unprefixed one
+        let statements = vec![Statement::Reexport { name: "EDITOR".to_string() }, Statement::Unset("OLD".to_string())];
+        assert_eq!(render_statements(statements, &options), "$env.APP_EDITOR = $env.EDITOR\nhide-env APP_OLD");
+        let options = RenderOptions { format: Format::Tsv, ..options };
+        assert_eq!(render(parse_posix_exports(input), &options), "APP_PORT\t8080\nAPP_HOST\tlocalhost");
+    }
+
+    #[test]
+    fn test_typed_load_env() {
+        let parse_options = ParseOptions { typed: true, ..Default::default() };
+        let options = RenderOptions { typed: true, load_env: true, ..Default::default() };
+        let input = "export PORT=8080 DEBUG=true R=0.5 Q=\"8080\" NAME=web";
+        assert_eq!(
+            exports_to_nushell_with(parse_posix_exports_with(input, &parse_options), &options),
+            "load-env {\n  PORT: 8080,\n  DEBUG: true,\n  R: 0.5,\n  Q: \"8080\",\n  NAME: \"web\"\n}"
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use crate::expand::{matching_brace, matching_paren};
+use crate::options::ParseOptions;
+
+// a shell word broken into the parts that came out of quoting or escapes,
+// which are taken literally, and the parts that may still be expanded
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Piece {
+    Literal(String),
+    Expandable(String),
+}
+
+// split a raw shell word (quotes and all) into pieces
+pub(crate) fn split_word(raw: &str, options: &ParseOptions) -> Vec<Piece> {
+    let mut pieces = Pieces::default();
+    let mut chars = raw.char_indices().peekable();
+
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            // pasted Windows paths keep their backslashes
+            '\\' if options.windows_paths => pieces.literal('\\'),
+            '\\' => match chars.next() {
+                Some((_, next)) => pieces.literal(next),
+                None => pieces.literal('\\'),
+            },
+            '\'' => {
+                // single quotes preserve everything literally
+                for (_, c) in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    pieces.literal(c);
+                }
+            }
+            '"' => {
+                // in double quotes, handle escape sequences
+                while let Some((j, c)) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some((_, e @ ('"' | '\\' | '$' | '`'))) => pieces.literal(e),
+                            // a line continuation: both go
+                            Some((_, '\n')) => {}
+                            Some((_, 'n')) => pieces.literal('\n'),
+                            Some((_, 't')) => pieces.literal('\t'),
+                            Some((_, 'r')) => pieces.literal('\r'),
+                            Some((_, other)) => {
+                                pieces.literal('\\');
+                                pieces.literal(other);
+                            }
+                            None => pieces.literal('\\'),
+                        },
+                        '$' => {
+                            let len = substitution_len(&raw[j..]);
+                            pieces.expandable(&raw[j..j + len]);
+                            skip(&mut chars, raw[j + 1..j + len].chars().count());
+                        }
+                        c => pieces.expandable_char(c),
+                    }
+                }
+            }
+            '$' if options.printf_q && chars.peek().map(|&(_, c)| c) == Some('\'') => {
+                chars.next();
+                let start = i + 2;
+                let end = ansi_c_end(&raw[start..]).map(|e| start + e).unwrap_or(raw.len());
+                pieces.literal_str(&decode_ansi_c(&raw[start..end]));
+                skip(&mut chars, raw[start..end].chars().count() + usize::from(end < raw.len()));
+            }
+            '$' => {
+                let len = substitution_len(&raw[i..]);
+                pieces.expandable(&raw[i..i + len]);
+                skip(&mut chars, raw[i + 1..i + len].chars().count());
+            }
+            c => pieces.expandable_char(c),
+        }
+    }
+
+    pieces.finish()
+}
+
+// length of the `$...` at the start of `text`: a whole balanced `$(...)` or
+// `${...}` is kept together, anything else is just the `$`
+fn substitution_len(text: &str) -> usize {
+    let close = if text.starts_with("$(") {
+        matching_paren(text, 1)
+    } else if text.starts_with("${") {
+        matching_brace(text, 1)
+    } else {
+        None
+    };
+    close.map(|c| c + 1).unwrap_or(1)
+}
+
+fn skip(chars: &mut impl Iterator, count: usize) {
+    for _ in 0..count {
+        chars.next();
+    }
+}
+
+// byte offset of the `'` ending a `$'...'` body, skipping `\'`
+fn ansi_c_end(body: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in body.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\'' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// decode the backslash escapes of a `$'...'` body
+fn decode_ansi_c(body: &str) -> String {
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('a') => out.push('\x07'),
+            Some('b') => out.push('\x08'),
+            Some('e' | 'E') => out.push('\x1b'),
+            Some('f') => out.push('\x0c'),
+            Some('v') => out.push('\x0b'),
+            Some(d @ '0'..='7') => {
+                let mut code = d.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                out.extend(char::from_u32(code));
+            }
+            Some(kind @ ('x' | 'u' | 'U')) => {
+                let max = match kind {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+                let mut digits = String::new();
+                while digits.len() < max && chars.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                    digits.extend(chars.next());
+                }
+                match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+                    Some(decoded) => out.push(decoded),
+                    None => {
+                        out.push('\\');
+                        out.push(kind);
+                        out.push_str(&digits);
+                    }
+                }
+            }
+            Some(other @ ('\\' | '\'' | '"' | '?')) => out.push(other),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+
+    out
+}
+
+// decode `%XX` sequences, as in URL-encoded connection strings; a `%` not
+// followed by two hex digits stays as it is, and so does the whole value
+// if the decoded bytes aren't UTF-8
+pub(crate) fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .filter(|h| bytes[i] == b'%' && h.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).unwrap_or_else(|_| value.to_string())
+}
+
+// collects pieces, merging neighbours of the same kind
+#[derive(Default)]
+struct Pieces(Vec<Piece>);
+
+impl Pieces {
+    fn literal(&mut self, c: char) {
+        match self.0.last_mut() {
+            Some(Piece::Literal(text)) => text.push(c),
+            _ => self.0.push(Piece::Literal(c.to_string())),
+        }
+    }
+
+    fn literal_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.literal(c);
+        }
+    }
+
+    fn expandable_char(&mut self, c: char) {
+        match self.0.last_mut() {
+            Some(Piece::Expandable(text)) => text.push(c),
+            _ => self.0.push(Piece::Expandable(c.to_string())),
+        }
+    }
+
+    fn expandable(&mut self, s: &str) {
+        for c in s.chars() {
+            self.expandable_char(c);
+        }
+    }
+
+    fn finish(self) -> Vec<Piece> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn posix() -> ParseOptions {
+        ParseOptions::default()
+    }
+
+    fn bash() -> ParseOptions {
+        ParseOptions { printf_q: true, ..Default::default() }
+    }
+
+    fn lit(s: &str) -> Piece {
+        Piece::Literal(s.to_string())
+    }
+
+    fn exp(s: &str) -> Piece {
+        Piece::Expandable(s.to_string())
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b%2Fc"), "a b/c");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        assert_eq!(percent_decode("50%"), "50%");
+        assert_eq!(percent_decode("x%2"), "x%2");
+        assert_eq!(percent_decode("%zz%+1"), "%zz%+1");
+        assert_eq!(percent_decode("bad%FF"), "bad%FF");
+    }
+
+    #[test]
+    fn test_split_mixed_quoting() {
+        assert_eq!(split_word(r#"a'$b'"$c\$d"e"#, &posix()), vec![exp("a"), lit("$b"), exp("$c"), lit("$"), exp("de")]);
+    }
+
+    #[test]
+    fn test_split_unquoted_backslashes() {
+        assert_eq!(split_word(r"a\ b", &posix()), vec![exp("a"), lit(" "), exp("b")]);
+        assert_eq!(split_word(r"\$x", &posix()), vec![lit("$"), exp("x")]);
+    }
+
+    #[test]
+    fn test_split_keeps_substitutions_together() {
+        assert_eq!(split_word(r#"${X:-'a b'}"#, &posix()), vec![exp("${X:-'a b'}")]);
+    }
+
+    #[test]
+    fn test_ansi_c_quoting() {
+        assert_eq!(split_word(r"$'a\tb\x41\e'", &bash()), vec![lit("a\tbA\x1b")]);
+        assert_eq!(split_word(r"$'it\'s'", &bash()), vec![lit("it's")]);
+        assert_eq!(split_word(r"$'a'", &posix()), vec![exp("$"), lit("a")]);
+    }
+
+    #[test]
+    fn test_windows_paths_keep_backslashes() {
+        let options = ParseOptions { windows_paths: true, ..Default::default() };
+        assert_eq!(split_word(r"C:\Users\me", &options), vec![exp("C:"), lit(r"\"), exp("Users"), lit(r"\"), exp("me")]);
+    }
+}